@@ -0,0 +1,59 @@
+use crate::host::HostError;
+use crate::tls::TlsError;
+use std::fmt;
+use std::io;
+
+#[derive(Debug)]
+pub enum ClusterError {
+    MissingAddress(String),
+    MissingGroup(String),
+    IncompleteGroup(String, usize, usize),
+    TlsError(TlsError),
+    HostError(HostError),
+    IOError(io::Error),
+}
+
+impl From<TlsError> for ClusterError {
+    fn from(error: TlsError) -> Self {
+        ClusterError::TlsError(error)
+    }
+}
+
+impl From<HostError> for ClusterError {
+    fn from(error: HostError) -> Self {
+        ClusterError::HostError(error)
+    }
+}
+
+impl From<io::Error> for ClusterError {
+    fn from(error: io::Error) -> Self {
+        ClusterError::IOError(error)
+    }
+}
+
+impl fmt::Display for ClusterError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ClusterError::MissingAddress(instance_id) => {
+                write!(
+                    f,
+                    "instance {} has no address of the configured family",
+                    instance_id
+                )
+            }
+            ClusterError::MissingGroup(manager_id) => {
+                write!(f, "members of instance group {} are unknown", manager_id)
+            }
+            ClusterError::IncompleteGroup(group_id, members, size) => {
+                write!(
+                    f,
+                    "only {} of the {} members of instance group {} are known",
+                    members, size, group_id
+                )
+            }
+            ClusterError::TlsError(error) => write!(f, "TLS error: {}", error),
+            ClusterError::HostError(error) => write!(f, "{}", error),
+            ClusterError::IOError(error) => write!(f, "I/O error: {}", error),
+        }
+    }
+}
@@ -0,0 +1,338 @@
+use crate::cluster::ClusterError;
+use crate::host;
+use crate::provider::{AddressFamily, CloudInstance, CloudInstanceGroup};
+use crate::secret::Secret;
+use crate::tls::{self, CertificateAuthority};
+use log::{info, warn};
+use serde::Deserialize;
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+
+const ENV_FILE: &str = "etcd.env";
+const CA_CERTIFICATE_FILE: &str = "ca.crt";
+const SERVER_CERTIFICATE_FILE: &str = "server.crt";
+const SERVER_KEY_FILE: &str = "server.key";
+const PEER_CERTIFICATE_FILE: &str = "peer.crt";
+const PEER_KEY_FILE: &str = "peer.key";
+
+// etcd refuses to start with a data directory readable by others
+const DATA_DIRECTORY_MODE: u32 = 0o700;
+
+#[derive(Clone, Copy, Deserialize, Debug, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum EtcdClusterState {
+    #[default]
+    New,
+    Existing,
+}
+
+impl EtcdClusterState {
+    fn as_str(&self) -> &'static str {
+        match self {
+            EtcdClusterState::New => "new",
+            EtcdClusterState::Existing => "existing",
+        }
+    }
+}
+
+#[derive(Clone, Deserialize, Debug)]
+pub struct EtcdConfiguration {
+    #[serde(default = "default_data_dir")]
+    pub data_dir: String,
+    // Where the environment file and TLS material are written
+    #[serde(default = "default_config_dir")]
+    pub config_dir: String,
+    #[serde(default = "default_service")]
+    pub service: String,
+    #[serde(default = "default_client_port")]
+    pub client_port: u16,
+    #[serde(default = "default_peer_port")]
+    pub peer_port: u16,
+    #[serde(default)]
+    pub initial_cluster_state: EtcdClusterState,
+    // Public address family used to reach the peers
+    #[serde(default)]
+    pub address_family: AddressFamily,
+    // PEM encoded CA, enabling TLS between peers and for clients
+    pub ca_certificate: Option<String>,
+    pub ca_key: Option<Secret>,
+    #[serde(default = "default_certificate_validity_days")]
+    pub certificate_validity_days: u32,
+    // Additional names for the client certificate (e.g. a load balancer)
+    #[serde(default)]
+    pub extra_names: Vec<String>,
+    // Owner of the data directory and private keys, e.g. "etcd" or "etcd:etcd"
+    pub owner: Option<String>,
+}
+
+fn default_data_dir() -> String {
+    "/var/lib/etcd".to_string()
+}
+
+fn default_config_dir() -> String {
+    "/run/instance-init/etcd".to_string()
+}
+
+fn default_service() -> String {
+    "etcd".to_string()
+}
+
+fn default_client_port() -> u16 {
+    2379
+}
+
+fn default_peer_port() -> u16 {
+    2380
+}
+
+fn default_certificate_validity_days() -> u32 {
+    365
+}
+
+impl EtcdConfiguration {
+    fn address<'a>(&self, instance: &'a CloudInstance) -> Result<&'a str, ClusterError> {
+        self.address_family
+            .address(instance)
+            .ok_or_else(|| ClusterError::MissingAddress(instance.instance_id.clone()))
+    }
+
+    fn loopback(&self) -> &'static str {
+        match self.address_family {
+            AddressFamily::Ipv4 => "127.0.0.1",
+            AddressFamily::Ipv6 => "::1",
+        }
+    }
+
+    fn url(&self, address: &str, port: u16) -> String {
+        let scheme = match self.ca_certificate.is_some() {
+            true => "https",
+            false => "http",
+        };
+
+        match address.contains(':') {
+            true => format!("{}://[{}]:{}", scheme, address, port),
+            false => format!("{}://{}:{}", scheme, address, port),
+        }
+    }
+}
+
+// Members sorted by instance id, or the instance alone when not part of a pool.
+// A pool member without its whole group would bootstrap a cluster missing some
+// members, or of its own.
+fn members<'a>(
+    instance: &'a CloudInstance,
+    group: Option<&'a CloudInstanceGroup>,
+) -> Result<Vec<&'a CloudInstance>, ClusterError> {
+    let mut members = match (group, &instance.manager_id) {
+        (Some(group), _) if group.instances.len() != group.size => {
+            return Err(ClusterError::IncompleteGroup(
+                group.instance_group_id.clone(),
+                group.instances.len(),
+                group.size,
+            ))
+        }
+        (Some(group), _) if !group.instances.is_empty() => {
+            group.instances.iter().collect::<Vec<_>>()
+        }
+        (_, Some(manager_id)) => return Err(ClusterError::MissingGroup(manager_id.clone())),
+        _ => vec![instance],
+    };
+    members.sort_by(|a, b| a.instance_id.cmp(&b.instance_id));
+    Ok(members)
+}
+
+fn initial_cluster(
+    configuration: &EtcdConfiguration,
+    members: &[&CloudInstance],
+) -> Result<String, ClusterError> {
+    let mut peers = Vec::new();
+    for member in members {
+        let address = configuration.address(member)?;
+        peers.push(format!(
+            "{}={}",
+            member.hostname,
+            configuration.url(address, configuration.peer_port)
+        ));
+    }
+
+    Ok(peers.join(","))
+}
+
+fn env_file(
+    configuration: &EtcdConfiguration,
+    instance: &CloudInstance,
+    group: Option<&CloudInstanceGroup>,
+) -> Result<String, ClusterError> {
+    let address = configuration.address(instance)?;
+    let members = members(instance, group)?;
+    let token = match group {
+        Some(group) => group.instance_group_id.as_str(),
+        None => instance.instance_id.as_str(),
+    };
+
+    let peer_url = configuration.url(address, configuration.peer_port);
+    let client_url = configuration.url(address, configuration.client_port);
+    let loopback_url = configuration.url(configuration.loopback(), configuration.client_port);
+
+    let mut variables = vec![
+        ("ETCD_NAME", instance.hostname.clone()),
+        ("ETCD_DATA_DIR", configuration.data_dir.clone()),
+        (
+            "ETCD_INITIAL_CLUSTER",
+            initial_cluster(configuration, &members)?,
+        ),
+        (
+            "ETCD_INITIAL_CLUSTER_STATE",
+            configuration.initial_cluster_state.as_str().to_string(),
+        ),
+        ("ETCD_INITIAL_CLUSTER_TOKEN", token.to_string()),
+        ("ETCD_INITIAL_ADVERTISE_PEER_URLS", peer_url.clone()),
+        ("ETCD_LISTEN_PEER_URLS", peer_url),
+        (
+            "ETCD_LISTEN_CLIENT_URLS",
+            format!("{},{}", client_url, loopback_url),
+        ),
+        ("ETCD_ADVERTISE_CLIENT_URLS", client_url),
+    ];
+
+    if configuration.ca_certificate.is_some() {
+        let path = |file: &str| {
+            Path::new(&configuration.config_dir)
+                .join(file)
+                .display()
+                .to_string()
+        };
+        variables.extend([
+            ("ETCD_TRUSTED_CA_FILE", path(CA_CERTIFICATE_FILE)),
+            ("ETCD_CERT_FILE", path(SERVER_CERTIFICATE_FILE)),
+            ("ETCD_KEY_FILE", path(SERVER_KEY_FILE)),
+            ("ETCD_CLIENT_CERT_AUTH", "true".to_string()),
+            ("ETCD_PEER_TRUSTED_CA_FILE", path(CA_CERTIFICATE_FILE)),
+            ("ETCD_PEER_CERT_FILE", path(PEER_CERTIFICATE_FILE)),
+            ("ETCD_PEER_KEY_FILE", path(PEER_KEY_FILE)),
+            ("ETCD_PEER_CLIENT_CERT_AUTH", "true".to_string()),
+        ]);
+    }
+
+    Ok(variables
+        .into_iter()
+        .map(|(name, value)| format!("{}={}\n", name, value))
+        .collect())
+}
+
+fn write_tls_material(
+    configuration: &EtcdConfiguration,
+    instance: &CloudInstance,
+    certificate: &str,
+    key: &Secret,
+) -> Result<(), ClusterError> {
+    let authority = CertificateAuthority::from_pem(certificate, key)?;
+    let directory = Path::new(&configuration.config_dir);
+    let owner = configuration.owner.as_deref();
+
+    // Clients may connect through the loopback as well
+    let mut client_names = configuration.extra_names.clone();
+    client_names.extend([
+        "localhost".to_string(),
+        configuration.loopback().to_string(),
+    ]);
+
+    let server = tls::issue_certificate(
+        &instance.hostname,
+        &tls::instance_names(instance, &client_names),
+        configuration.certificate_validity_days,
+        Some(&authority),
+    )?;
+    let peer = tls::issue_certificate(
+        &instance.hostname,
+        &tls::instance_names(instance, &[]),
+        configuration.certificate_validity_days,
+        Some(&authority),
+    )?;
+
+    tls::write_pem(
+        &directory.join(CA_CERTIFICATE_FILE),
+        authority.certificate_pem(),
+        false,
+        owner,
+    )?;
+    tls::write_pem(
+        &directory.join(SERVER_CERTIFICATE_FILE),
+        &server.certificate,
+        false,
+        owner,
+    )?;
+    tls::write_pem(
+        &directory.join(SERVER_KEY_FILE),
+        server.private_key.expose(),
+        true,
+        owner,
+    )?;
+    tls::write_pem(
+        &directory.join(PEER_CERTIFICATE_FILE),
+        &peer.certificate,
+        false,
+        owner,
+    )?;
+    tls::write_pem(
+        &directory.join(PEER_KEY_FILE),
+        peer.private_key.expose(),
+        true,
+        owner,
+    )?;
+
+    Ok(())
+}
+
+fn ensure_data_directory(configuration: &EtcdConfiguration) -> Result<(), ClusterError> {
+    let data_dir = Path::new(&configuration.data_dir);
+    if data_dir.exists() {
+        return Ok(());
+    }
+
+    fs::create_dir_all(data_dir)?;
+    fs::set_permissions(data_dir, fs::Permissions::from_mode(DATA_DIRECTORY_MODE))?;
+    if let Some(owner) = &configuration.owner {
+        host::set_owner(data_dir, owner)?;
+    }
+
+    Ok(())
+}
+
+// Writes the etcd environment and TLS material, and points the etcd service at them
+pub fn bootstrap_etcd(
+    configuration: &EtcdConfiguration,
+    instance: &CloudInstance,
+    group: Option<&CloudInstanceGroup>,
+) -> Result<(), ClusterError> {
+    let directory = Path::new(&configuration.config_dir);
+    fs::create_dir_all(directory)?;
+
+    let env = env_file(configuration, instance, group)?;
+
+    match (&configuration.ca_certificate, &configuration.ca_key) {
+        (Some(certificate), Some(key)) => {
+            write_tls_material(configuration, instance, certificate, key)?;
+            info!("etcd TLS material written to {}", directory.display());
+        }
+        (Some(_), None) => {
+            return Err(
+                tls::TlsError::InvalidAuthority("missing CA private key".to_string()).into(),
+            );
+        }
+        // Peers and clients then reach etcd in clear on public addresses
+        _ => warn!("No CA given, etcd peers and clients won't use TLS"),
+    }
+
+    let env_path = directory.join(ENV_FILE);
+    fs::write(&env_path, env)?;
+    ensure_data_directory(configuration)?;
+
+    host::write_service_dropin(
+        &configuration.service,
+        &format!("[Service]\nEnvironmentFile={}\n", env_path.display()),
+    )?;
+
+    Ok(())
+}
@@ -0,0 +1,5 @@
+mod error;
+mod etcd;
+
+pub use error::ClusterError;
+pub use etcd::{bootstrap_etcd, EtcdConfiguration};
@@ -0,0 +1,174 @@
+use crate::configuration::error::ConfigurationError;
+use crate::secret;
+use age::armor::ArmoredReader;
+use age::{Decryptor, IdentityFile, IdentityFileEntry};
+use log::{debug, info};
+use std::io::{BufReader, Read};
+use std::path::Path;
+use toml::Value;
+
+// Configuration values may be encrypted for an age X25519 recipient:
+//
+//   api_secret = { encrypted = """
+//   -----BEGIN AGE ENCRYPTED FILE-----
+//   ...
+//   -----END AGE ENCRYPTED FILE-----
+//   """ }
+//
+// They are decrypted in memory with the identity provisioned on the instance.
+
+const ENCRYPTED_KEY: &str = "encrypted";
+
+// Instance-local key first, then the one baked into the image
+const IDENTITY_PATHS: [&str; 2] = [
+    "/etc/instance-init/identity",
+    "/usr/lib/instance-init/identity",
+];
+
+fn load_identities() -> Result<Vec<age::x25519::Identity>, ConfigurationError> {
+    let path = IDENTITY_PATHS
+        .iter()
+        .map(Path::new)
+        .find(|path| path.exists())
+        .ok_or_else(|| ConfigurationError::DecryptionError("no identity available".to_string()))?;
+    debug!("Loading decryption identity from {}", path.display());
+
+    let file = std::fs::File::open(path).map_err(|err| {
+        ConfigurationError::DecryptionError(format!("{}: {}", path.display(), err))
+    })?;
+    let identities = IdentityFile::from_buffer(BufReader::new(file))
+        .map_err(|err| ConfigurationError::DecryptionError(format!("{}: {}", path.display(), err)))?
+        .into_identities()
+        .into_iter()
+        .map(|IdentityFileEntry::Native(identity)| identity)
+        .collect::<Vec<_>>();
+
+    match identities.is_empty() {
+        true => Err(ConfigurationError::DecryptionError(format!(
+            "{}: no X25519 identity",
+            path.display()
+        ))),
+        false => Ok(identities),
+    }
+}
+
+fn decrypt_value(
+    encrypted: &str,
+    identities: &[age::x25519::Identity],
+) -> Result<String, ConfigurationError> {
+    let failed = |err: &dyn std::fmt::Display| ConfigurationError::DecryptionError(err.to_string());
+
+    let decryptor = match Decryptor::new(ArmoredReader::new(encrypted.trim().as_bytes())) {
+        Ok(Decryptor::Recipients(decryptor)) => decryptor,
+        Ok(Decryptor::Passphrase(_)) => {
+            return Err(ConfigurationError::DecryptionError(
+                "passphrase encryption is not supported".to_string(),
+            ))
+        }
+        Err(err) => return Err(failed(&err)),
+    };
+
+    let mut reader = decryptor
+        .decrypt(
+            identities
+                .iter()
+                .map(|identity| identity as &dyn age::Identity),
+        )
+        .map_err(|err| failed(&err))?;
+
+    let mut decrypted = String::new();
+    reader
+        .read_to_string(&mut decrypted)
+        .map_err(|err| failed(&err))?;
+
+    // Whatever it ends up in, e.g. a plain string field, it is a secret
+    secret::register(&decrypted);
+    Ok(decrypted)
+}
+
+fn encrypted_value(value: &Value) -> Option<&str> {
+    match value.as_table() {
+        Some(table) if table.len() == 1 => table.get(ENCRYPTED_KEY)?.as_str(),
+        _ => None,
+    }
+}
+
+fn contains_encrypted_values(value: &Value) -> bool {
+    match value {
+        Value::Table(table) => {
+            encrypted_value(value).is_some() || table.values().any(contains_encrypted_values)
+        }
+        Value::Array(values) => values.iter().any(contains_encrypted_values),
+        _ => false,
+    }
+}
+
+fn decrypt_document(
+    value: Value,
+    identities: &[age::x25519::Identity],
+) -> Result<Value, ConfigurationError> {
+    if let Some(encrypted) = encrypted_value(&value) {
+        return Ok(Value::String(decrypt_value(encrypted, identities)?));
+    }
+
+    Ok(match value {
+        Value::Table(table) => Value::Table(
+            table
+                .into_iter()
+                .map(|(key, value)| Ok((key, decrypt_document(value, identities)?)))
+                .collect::<Result<_, ConfigurationError>>()?,
+        ),
+        Value::Array(values) => Value::Array(
+            values
+                .into_iter()
+                .map(|value| decrypt_document(value, identities))
+                .collect::<Result<_, _>>()?,
+        ),
+        other => other,
+    })
+}
+
+// Replaces every encrypted value of the document by its plaintext
+pub fn decrypt(document: Value) -> Result<Value, ConfigurationError> {
+    if !contains_encrypted_values(&document) {
+        return Ok(document);
+    }
+
+    info!("Decrypting encrypted configuration values");
+    let identities = load_identities()?;
+    decrypt_document(document, &identities)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use age::armor::{ArmoredWriter, Format};
+    use std::io::Write;
+
+    fn encrypt(plaintext: &str, recipient: age::x25519::Recipient) -> String {
+        let encryptor = age::Encryptor::with_recipients(vec![Box::new(recipient)]).unwrap();
+        let mut encrypted = Vec::new();
+        let armored = ArmoredWriter::wrap_output(&mut encrypted, Format::AsciiArmor).unwrap();
+        let mut writer = encryptor.wrap_output(armored).unwrap();
+        writer.write_all(plaintext.as_bytes()).unwrap();
+        writer
+            .finish()
+            .and_then(|armored| armored.finish())
+            .unwrap();
+        String::from_utf8(encrypted).unwrap()
+    }
+
+    #[test]
+    fn decrypted_values_are_redacted() {
+        let identity = age::x25519::Identity::generate();
+        let plaintext = "decrypted-api-secret";
+        let encrypted = encrypt(plaintext, identity.to_public());
+
+        let decrypted = decrypt_value(&encrypted, &[identity]).unwrap();
+        assert_eq!(decrypted, plaintext);
+        assert_eq!(
+            secret::redact(&format!("password={}", decrypted)),
+            "password=***"
+        );
+    }
+}
@@ -0,0 +1,40 @@
+use std::fmt;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum ConfigurationError {
+    ParseError(String),
+    IncludeError(String),
+    IntegrityError(String),
+    SignatureError(String),
+    DecryptionError(String),
+    LimitExceeded(String),
+}
+
+impl fmt::Display for ConfigurationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigurationError::ParseError(reason) => {
+                write!(f, "invalid configuration: {}", reason)
+            }
+            ConfigurationError::IncludeError(reason) => {
+                write!(f, "unable to include configuration: {}", reason)
+            }
+            ConfigurationError::IntegrityError(reason) => {
+                write!(f, "integrity check failed: {}", reason)
+            }
+            ConfigurationError::SignatureError(reason) => {
+                write!(f, "signature verification failed: {}", reason)
+            }
+            ConfigurationError::DecryptionError(reason) => {
+                write!(f, "unable to decrypt value: {}", reason)
+            }
+            ConfigurationError::LimitExceeded(reason) => write!(f, "limit exceeded: {}", reason),
+        }
+    }
+}
+
+impl From<toml::de::Error> for ConfigurationError {
+    fn from(error: toml::de::Error) -> Self {
+        ConfigurationError::ParseError(error.to_string())
+    }
+}
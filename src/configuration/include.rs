@@ -0,0 +1,241 @@
+use crate::configuration::error::ConfigurationError;
+use crate::http_client::{HttpClient, HttpError, HttpRequest, RetryPolicy};
+use log::{debug, info};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::fs::Permissions;
+use std::future::Future;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+use std::pin::Pin;
+use std::time::Duration;
+use tokio::fs::{DirBuilder, File};
+use tokio::io::AsyncReadExt;
+use toml::Value;
+
+const INCLUDE_KEY: &str = "include";
+const INCLUDE_DIRECTORY: &str = "/run/instance-init/include";
+const INCLUDE_TIMEOUT_SECS: u64 = 10;
+const INCLUDE_RETRY_ATTEMPTS: u32 = 3;
+const INCLUDE_MAX_DEPTH: usize = 4;
+const INCLUDE_MAX_SIZE: usize = 64 * 1024;
+// Fragments may hold secrets
+const INCLUDE_DIRECTORY_MODE: u32 = 0o700;
+
+#[derive(Clone, Deserialize, Debug)]
+#[serde(untagged)]
+pub enum IncludeConfiguration {
+    Url(String),
+    Pinned { url: String, sha256: String },
+}
+
+impl IncludeConfiguration {
+    pub fn url(&self) -> &str {
+        match self {
+            IncludeConfiguration::Url(url) => url,
+            IncludeConfiguration::Pinned { url, .. } => url,
+        }
+    }
+
+    pub fn sha256(&self) -> Option<&str> {
+        match self {
+            IncludeConfiguration::Url(_) => None,
+            IncludeConfiguration::Pinned { sha256, .. } => Some(sha256),
+        }
+    }
+}
+
+fn sha256_hex(content: &[u8]) -> String {
+    Sha256::digest(content)
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+async fn fetch(
+    client: &HttpClient,
+    include: &IncludeConfiguration,
+    require_pin: bool,
+) -> Result<String, ConfigurationError> {
+    let url = include.url();
+    debug!("Fetching configuration fragment from {}", url);
+
+    // Local files are part of the image, remote ones must not bypass signed user-data
+    if require_pin && include.sha256().is_none() && !url.starts_with("file://") {
+        return Err(ConfigurationError::IntegrityError(format!(
+            "{} must be pinned with a sha256 when user-data is signed",
+            url
+        )));
+    }
+
+    // Plain HTTP can be tampered with on the way
+    if include.sha256().is_none() && url.starts_with("http://") {
+        return Err(ConfigurationError::IntegrityError(format!(
+            "{} must be pinned with a sha256 when not using https",
+            url
+        )));
+    }
+
+    if let Some(path) = url.strip_prefix("file://") {
+        read_file(url, Path::new(path), include.sha256()).await
+    } else if url.starts_with("https://") || url.starts_with("http://") {
+        download(client, url, include.sha256()).await
+    } else {
+        Err(ConfigurationError::IncludeError(format!(
+            "{}: unsupported scheme",
+            url
+        )))
+    }
+}
+
+// Files are checked against the size limit before being read, and not read past it
+// should they grow meanwhile
+async fn read_file(
+    url: &str,
+    path: &Path,
+    sha256: Option<&str>,
+) -> Result<String, ConfigurationError> {
+    let include_error =
+        |err: std::io::Error| ConfigurationError::IncludeError(format!("{}: {}", url, err));
+    let too_large = || {
+        ConfigurationError::LimitExceeded(format!(
+            "{} is larger than {} bytes",
+            url, INCLUDE_MAX_SIZE
+        ))
+    };
+
+    let file = File::open(path).await.map_err(include_error)?;
+    if file.metadata().await.map_err(include_error)?.len() > INCLUDE_MAX_SIZE as u64 {
+        return Err(too_large());
+    }
+
+    let mut content = String::new();
+    file.take(INCLUDE_MAX_SIZE as u64 + 1)
+        .read_to_string(&mut content)
+        .await
+        .map_err(include_error)?;
+    if content.len() > INCLUDE_MAX_SIZE {
+        return Err(too_large());
+    }
+
+    if let Some(expected) = sha256 {
+        let actual = sha256_hex(content.as_bytes());
+        if !actual.eq_ignore_ascii_case(expected) {
+            return Err(ConfigurationError::IntegrityError(format!(
+                "{} has sha256 {}, expected {}",
+                url, actual, expected
+            )));
+        }
+    }
+
+    Ok(content)
+}
+
+// Remote fragments are kept under the include directory for inspection, readable
+// by root only, size and checksum being verified while downloading
+async fn download(
+    client: &HttpClient,
+    url: &str,
+    sha256: Option<&str>,
+) -> Result<String, ConfigurationError> {
+    let include_error =
+        |err: std::io::Error| ConfigurationError::IncludeError(format!("{}: {}", url, err));
+
+    DirBuilder::new()
+        .recursive(true)
+        .mode(INCLUDE_DIRECTORY_MODE)
+        .create(INCLUDE_DIRECTORY)
+        .await
+        .map_err(include_error)?;
+    tokio::fs::set_permissions(
+        INCLUDE_DIRECTORY,
+        Permissions::from_mode(INCLUDE_DIRECTORY_MODE),
+    )
+    .await
+    .map_err(include_error)?;
+    let path = Path::new(INCLUDE_DIRECTORY).join(format!("{}.toml", sha256_hex(url.as_bytes())));
+
+    client
+        .download(HttpRequest::get(url.to_string()), &path, sha256)
+        .await
+        .map_err(|err| match err {
+            HttpError::ChecksumMismatch { .. } => {
+                ConfigurationError::IntegrityError(err.to_string())
+            }
+            HttpError::BodyTooLarge { .. } => ConfigurationError::LimitExceeded(err.to_string()),
+            _ => ConfigurationError::IncludeError(err.to_string()),
+        })?;
+
+    tokio::fs::read_to_string(&path)
+        .await
+        .map_err(include_error)
+}
+
+// Tables are merged recursively and arrays are concatenated, so that fragments
+// can contribute e.g. SSH keys; on conflicting values `overlay` wins.
+fn merge(base: Value, overlay: Value) -> Value {
+    match (base, overlay) {
+        (Value::Table(mut base), Value::Table(overlay)) => {
+            for (key, value) in overlay {
+                let value = match base.remove(&key) {
+                    Some(existing) => merge(existing, value),
+                    None => value,
+                };
+                base.insert(key, value);
+            }
+            Value::Table(base)
+        }
+        (Value::Array(mut base), Value::Array(overlay)) => {
+            base.extend(overlay);
+            Value::Array(base)
+        }
+        (_, overlay) => overlay,
+    }
+}
+
+fn resolve_document<'a>(
+    client: &'a HttpClient,
+    mut document: Value,
+    depth: usize,
+    require_pin: bool,
+) -> Pin<Box<dyn Future<Output = Result<Value, ConfigurationError>> + Send + 'a>> {
+    Box::pin(async move {
+        let includes = match document
+            .as_table_mut()
+            .and_then(|table| table.remove(INCLUDE_KEY))
+        {
+            Some(includes) => includes.try_into::<Vec<IncludeConfiguration>>()?,
+            None => return Ok(document),
+        };
+
+        if depth >= INCLUDE_MAX_DEPTH {
+            return Err(ConfigurationError::LimitExceeded(format!(
+                "includes nested deeper than {} levels",
+                INCLUDE_MAX_DEPTH
+            )));
+        }
+
+        let mut merged = Value::Table(toml::map::Map::new());
+        for include in includes {
+            info!("Including configuration from {}", include.url());
+            let fragment = toml::from_str(fetch(client, &include, require_pin).await?.as_str())?;
+            let fragment = resolve_document(client, fragment, depth + 1, require_pin).await?;
+            merged = merge(merged, fragment);
+        }
+
+        Ok(merge(merged, document))
+    })
+}
+
+// Fetches and merges the fragments listed under the `include` key, recursively.
+// With `require_pin`, remote fragments are only accepted with a sha256 pin.
+pub async fn resolve(document: Value, require_pin: bool) -> Result<Value, ConfigurationError> {
+    let mut client = HttpClient::new(INCLUDE_TIMEOUT_SECS);
+    client.set_max_body_size(INCLUDE_MAX_SIZE);
+    client.set_retry_policy(RetryPolicy::new(
+        INCLUDE_RETRY_ATTEMPTS,
+        Duration::from_secs(1),
+        Duration::from_secs(INCLUDE_TIMEOUT_SECS),
+    ));
+    resolve_document(&client, document, 0, require_pin).await
+}
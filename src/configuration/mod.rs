@@ -0,0 +1,182 @@
+mod encryption;
+mod error;
+mod include;
+mod signature;
+
+pub use error::ConfigurationError;
+
+use crate::cluster::EtcdConfiguration;
+use crate::network::WireguardConfiguration;
+use crate::provider::exoscale::ExoscaleCloudProviderConfiguration;
+use crate::provider::{CloudInstance, CloudInstanceGroup};
+use crate::template;
+use crate::tls::{AcmeConfiguration, LocalCertificateConfiguration, VaultConfiguration};
+use log::error;
+use serde::Deserialize;
+use serde_json::json;
+use std::collections::HashMap;
+use std::fmt;
+
+#[derive(Clone, Deserialize, Default)]
+pub struct CloudConfiguration {
+    #[serde(default = "default_provider_configuration")]
+    pub provider: ProviderConfiguration,
+
+    #[serde(default = "default_host_configuration")]
+    pub host: HostConfiguration,
+
+    #[serde(default = "default_cluster_configuration")]
+    pub cluster: ClusterConfiguration,
+
+    #[serde(default = "default_network_configuration")]
+    pub network: NetworkConfiguration,
+
+    #[serde(default = "default_certificates_configuration")]
+    pub tls: CertificatesConfiguration,
+
+    // Parsed document, kept to render templated values once instance facts are known.
+    // Secrets are decrypted in it, so it is left out of Debug.
+    #[serde(skip)]
+    source: Option<toml::Value>,
+}
+
+impl fmt::Debug for CloudConfiguration {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CloudConfiguration")
+            .field("provider", &self.provider)
+            .field("host", &self.host)
+            .field("cluster", &self.cluster)
+            .field("network", &self.network)
+            .field("tls", &self.tls)
+            .finish_non_exhaustive()
+    }
+}
+
+impl CloudConfiguration {
+    pub async fn load(configuration: &str) -> Option<CloudConfiguration> {
+        let source = match Self::resolve(configuration).await {
+            Ok(source) => source,
+            Err(e) => {
+                error!("Error loading configuration file: {}", e);
+                return None;
+            }
+        };
+
+        Self::from_value(source)
+    }
+
+    async fn resolve(configuration: &str) -> Result<toml::Value, ConfigurationError> {
+        let (configuration, signed) = signature::verify(configuration)?;
+        let source = toml::from_str(configuration)?;
+        let source = include::resolve(source, signed).await?;
+        encryption::decrypt(source)
+    }
+
+    fn from_value(source: toml::Value) -> Option<CloudConfiguration> {
+        match source.clone().try_into::<CloudConfiguration>() {
+            Ok(mut configuration) => {
+                configuration.source = Some(source);
+                Some(configuration)
+            }
+            Err(e) => {
+                error!("Error parsing configuration file: {}", e);
+                None
+            }
+        }
+    }
+
+    pub fn render(
+        &self,
+        instance: &CloudInstance,
+        group: Option<&CloudInstanceGroup>,
+    ) -> Option<CloudConfiguration> {
+        let source = match &self.source {
+            Some(source) => source,
+            None => return Some(self.clone()),
+        };
+
+        let facts = json!({
+            "instance": instance,
+            "group": group,
+        });
+
+        match template::render(source, &facts) {
+            Ok(source) => Self::from_value(source),
+            Err(e) => {
+                error!("Error rendering configuration file: {}", e);
+                None
+            }
+        }
+    }
+}
+
+pub fn default_provider_configuration() -> ProviderConfiguration {
+    ProviderConfiguration::default()
+}
+
+pub fn default_host_configuration() -> HostConfiguration {
+    HostConfiguration::default()
+}
+
+pub fn default_cluster_configuration() -> ClusterConfiguration {
+    ClusterConfiguration::default()
+}
+
+pub fn default_network_configuration() -> NetworkConfiguration {
+    NetworkConfiguration::default()
+}
+
+pub fn default_certificates_configuration() -> CertificatesConfiguration {
+    CertificatesConfiguration::default()
+}
+
+#[derive(Clone, Deserialize, Debug, Default)]
+pub struct ProviderConfiguration {
+    pub exoscale: Option<ExoscaleCloudProviderConfiguration>,
+}
+
+#[derive(Clone, Deserialize, Debug, Default)]
+pub struct ClusterConfiguration {
+    pub etcd: Option<EtcdConfiguration>,
+}
+
+#[derive(Clone, Deserialize, Debug, Default)]
+pub struct NetworkConfiguration {
+    pub wireguard: Option<WireguardConfiguration>,
+}
+
+// Certificates provisioned for the services of the instance
+#[derive(Clone, Deserialize, Debug, Default)]
+pub struct CertificatesConfiguration {
+    pub vault: Option<VaultConfiguration>,
+    pub acme: Option<AcmeConfiguration>,
+    pub local: Option<LocalCertificateConfiguration>,
+}
+
+#[derive(Clone, Deserialize, Debug, Default)]
+pub struct HostConfiguration {
+    pub user: HashMap<String, UserConfiguration>,
+    // Keeps a block of /etc/hosts with the instance group members
+    #[serde(default)]
+    pub manage_hosts: bool,
+    #[serde(default)]
+    pub hooks: Vec<HookConfiguration>,
+}
+
+#[derive(Clone, Deserialize, Debug, Default)]
+pub struct HookConfiguration {
+    pub command: String,
+    // Only run on the leader of the instance group (or a standalone instance)
+    #[serde(default)]
+    pub leader_only: bool,
+}
+
+#[derive(Clone, Deserialize, Debug, Default)]
+pub struct UserConfiguration {
+    pub ssh: UserSSHConfiguration,
+}
+
+#[derive(Clone, Deserialize, Debug, Default)]
+pub struct UserSSHConfiguration {
+    pub authorized_keys: Vec<String>,
+}
@@ -0,0 +1,257 @@
+use crate::configuration::error::ConfigurationError;
+use ed25519_dalek::{Signature, VerifyingKey, PUBLIC_KEY_LENGTH};
+use log::{debug, error, info};
+use std::fs;
+use std::io;
+use std::path::Path;
+
+// User-data may end with a detached ed25519 signature of everything before it:
+//
+//   # instance-init-signature: ed25519 <base64 signature>
+//
+// Being a comment, the signature line doesn't get in the way of TOML parsing.
+// Verification is enforced as soon as the image ships a trusted keys directory,
+// even an empty one.
+
+const TRUSTED_KEYS_DIRECTORY: &str = "/usr/lib/instance-init/trusted-keys";
+const SIGNATURE_PREFIX: &str = "# instance-init-signature:";
+const SIGNATURE_ALGORITHM: &str = "ed25519";
+
+// DER prefix of an ed25519 SubjectPublicKeyInfo, as written by `openssl pkey -pubout`
+const ED25519_SPKI_PREFIX: [u8; 12] = [
+    0x30, 0x2a, 0x30, 0x05, 0x06, 0x03, 0x2b, 0x65, 0x70, 0x03, 0x21, 0x00,
+];
+
+fn parse_trusted_key(content: &str) -> Option<VerifyingKey> {
+    let encoded: String = content
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with("-----"))
+        .collect();
+    let decoded = base64::decode(encoded).ok()?;
+
+    let key = match decoded.len() {
+        PUBLIC_KEY_LENGTH => decoded.as_slice(),
+        _ => decoded.strip_prefix(&ED25519_SPKI_PREFIX[..])?,
+    };
+
+    VerifyingKey::from_bytes(key.try_into().ok()?).ok()
+}
+
+// Returns None when the image doesn't enforce signatures, i.e. has no trusted keys
+// directory. Any other error reading it refuses the user-data. Key files that can't
+// be parsed are skipped: an image with no valid key refuses every user-data.
+fn load_trusted_keys(directory: &Path) -> Result<Option<Vec<VerifyingKey>>, ConfigurationError> {
+    let unreadable = |error: io::Error| {
+        ConfigurationError::SignatureError(format!(
+            "unable to read trusted keys {}: {}",
+            directory.display(),
+            error
+        ))
+    };
+
+    let entries = match fs::read_dir(directory) {
+        Ok(entries) => entries,
+        Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(error) => return Err(unreadable(error)),
+    };
+
+    let mut keys = Vec::new();
+    for entry in entries {
+        let path = entry.map_err(unreadable)?.path();
+        match fs::read_to_string(&path)
+            .ok()
+            .as_deref()
+            .and_then(parse_trusted_key)
+        {
+            Some(key) => {
+                debug!("Loaded trusted key {}", path.display());
+                keys.push(key);
+            }
+            None => error!("Ignoring invalid trusted key {}", path.display()),
+        }
+    }
+
+    Ok(Some(keys))
+}
+
+fn split_signature(user_data: &str) -> Option<(&str, &str)> {
+    let trimmed = user_data.trim_end();
+    let start = trimmed
+        .rfind('\n')
+        .map(|position| position + 1)
+        .unwrap_or(0);
+    let signature = trimmed[start..].strip_prefix(SIGNATURE_PREFIX)?;
+
+    Some((&user_data[..start], signature.trim()))
+}
+
+fn parse_signature(signature: &str) -> Result<Signature, ConfigurationError> {
+    let invalid = |reason: &str| ConfigurationError::SignatureError(reason.to_string());
+
+    let (algorithm, encoded) = signature
+        .split_once(char::is_whitespace)
+        .ok_or_else(|| invalid("malformed signature line"))?;
+    if algorithm != SIGNATURE_ALGORITHM {
+        return Err(invalid("unsupported signature algorithm"));
+    }
+
+    let decoded = base64::decode(encoded.trim()).map_err(|_| invalid("malformed signature"))?;
+    Signature::from_slice(&decoded).map_err(|_| invalid("malformed signature"))
+}
+
+// Returns the payload to parse, along with whether its signature was enforced.
+pub fn verify(user_data: &str) -> Result<(&str, bool), ConfigurationError> {
+    verify_with(user_data, Path::new(TRUSTED_KEYS_DIRECTORY))
+}
+
+fn verify_with<'a>(
+    user_data: &'a str,
+    directory: &Path,
+) -> Result<(&'a str, bool), ConfigurationError> {
+    let keys = match load_trusted_keys(directory)? {
+        Some(keys) => keys,
+        None => {
+            debug!("No trusted keys, skipping user-data signature verification");
+            return Ok((user_data, false));
+        }
+    };
+
+    let (payload, signature) = split_signature(user_data)
+        .ok_or_else(|| ConfigurationError::SignatureError("user-data is not signed".to_string()))?;
+    let signature = parse_signature(signature)?;
+
+    if !keys
+        .iter()
+        .any(|key| key.verify_strict(payload.as_bytes(), &signature).is_ok())
+    {
+        return Err(ConfigurationError::SignatureError(
+            "signature doesn't match any trusted key".to_string(),
+        ));
+    }
+
+    info!("User-data signature verified");
+    Ok((payload, true))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ed25519_dalek::{Signer, SigningKey};
+    use std::path::PathBuf;
+
+    const PAYLOAD: &str = "[host]\nmanage_hosts = true\n";
+
+    fn signing_key(seed: u8) -> SigningKey {
+        SigningKey::from_bytes(&[seed; 32])
+    }
+
+    fn sign(payload: &str, key: &SigningKey) -> String {
+        format!(
+            "{}{} {} {}\n",
+            payload,
+            SIGNATURE_PREFIX,
+            SIGNATURE_ALGORITHM,
+            base64::encode(key.sign(payload.as_bytes()).to_bytes())
+        )
+    }
+
+    fn pem(key: &SigningKey) -> String {
+        let mut der = ED25519_SPKI_PREFIX.to_vec();
+        der.extend(key.verifying_key().to_bytes());
+        format!(
+            "-----BEGIN PUBLIC KEY-----\n{}\n-----END PUBLIC KEY-----\n",
+            base64::encode(der)
+        )
+    }
+
+    // Trusted keys directory holding the given key files
+    fn trusted_keys(name: &str, files: &[(&str, String)]) -> PathBuf {
+        let directory = std::env::temp_dir().join(format!(
+            "instance-init-{}-trusted-keys-{}",
+            std::process::id(),
+            name
+        ));
+        let _ = fs::remove_dir_all(&directory);
+        fs::create_dir_all(&directory).unwrap();
+        for (file, content) in files {
+            fs::write(directory.join(file), content).unwrap();
+        }
+        directory
+    }
+
+    #[test]
+    fn splits_signature_line() {
+        let user_data = sign(PAYLOAD, &signing_key(1));
+        let (payload, signature) = split_signature(&user_data).unwrap();
+        assert_eq!(payload, PAYLOAD);
+        assert!(signature.starts_with("ed25519 "));
+
+        assert_eq!(split_signature(PAYLOAD), None);
+    }
+
+    #[test]
+    fn parses_pem_and_raw_keys() {
+        let key = signing_key(1);
+        let raw = base64::encode(key.verifying_key().to_bytes());
+
+        assert_eq!(parse_trusted_key(&pem(&key)), Some(key.verifying_key()));
+        assert_eq!(parse_trusted_key(&raw), Some(key.verifying_key()));
+        assert_eq!(parse_trusted_key("not a key"), None);
+    }
+
+    #[test]
+    fn verifies_signature_of_a_trusted_key() {
+        let key = signing_key(1);
+        let directory = trusted_keys("valid", &[("pem", pem(&key)), ("invalid", "x".into())]);
+
+        let user_data = sign(PAYLOAD, &key);
+        assert_eq!(
+            verify_with(&user_data, &directory).unwrap(),
+            (PAYLOAD, true)
+        );
+
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn rejects_tampered_or_misplaced_signatures() {
+        let key = signing_key(1);
+        let directory = trusted_keys("tampered", &[("pem", pem(&key))]);
+        let user_data = sign(PAYLOAD, &key);
+
+        let tampered = user_data.replace("true", "false");
+        assert!(verify_with(&tampered, &directory).is_err());
+
+        // The signature must be the last line
+        let appended = format!("{}[cluster]\n", user_data);
+        assert!(verify_with(&appended, &directory).is_err());
+
+        let unknown = sign(PAYLOAD, &signing_key(2));
+        assert!(verify_with(&unknown, &directory).is_err());
+
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn trusted_keys_directory_enforces_signatures() {
+        let missing = std::env::temp_dir().join(format!(
+            "instance-init-{}-trusted-keys-missing",
+            std::process::id()
+        ));
+        assert_eq!(verify_with(PAYLOAD, &missing).unwrap(), (PAYLOAD, false));
+
+        // Even empty, an existing directory refuses unsigned user-data
+        let empty = trusted_keys("empty", &[]);
+        assert_eq!(load_trusted_keys(&empty).unwrap(), Some(Vec::new()));
+        assert!(verify_with(PAYLOAD, &empty).is_err());
+        assert!(verify_with(&sign(PAYLOAD, &signing_key(1)), &empty).is_err());
+
+        // Not a directory
+        let file = empty.join("file");
+        fs::write(&file, "").unwrap();
+        assert!(load_trusted_keys(&file).is_err());
+
+        fs::remove_dir_all(&empty).unwrap();
+    }
+}
@@ -1,9 +1,15 @@
+use std::fmt;
 use std::io::Error;
 
+#[allow(clippy::enum_variant_names)]
 #[derive(Debug)]
 pub enum HostError {
     HostnameError,
     SSHSetupError,
+    NetworkSetupError,
+    HookError(String),
+    ServiceSetupError,
+    OwnerError(String),
     IOError(Error),
 }
 
@@ -11,4 +17,18 @@ impl From<Error> for HostError {
     fn from(error: Error) -> Self {
         Self::IOError(error)
     }
-}
\ No newline at end of file
+}
+
+impl fmt::Display for HostError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HostError::HostnameError => write!(f, "unable to set hostname"),
+            HostError::SSHSetupError => write!(f, "unable to set up SSH"),
+            HostError::NetworkSetupError => write!(f, "unable to set up network"),
+            HostError::HookError(command) => write!(f, "hook `{}` failed", command),
+            HostError::ServiceSetupError => write!(f, "unable to set up systemd service"),
+            HostError::OwnerError(name) => write!(f, "unknown user or group `{}`", name),
+            HostError::IOError(error) => write!(f, "I/O error: {}", error),
+        }
+    }
+}
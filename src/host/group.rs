@@ -0,0 +1,231 @@
+use crate::host::HostError;
+use crate::provider::{CloudInstance, CloudInstanceGroup};
+use log::debug;
+use serde::Serialize;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+const RUNTIME_DIRECTORY: &str = "/run/instance-init";
+const GROUP_JSON_FILE: &str = "group.json";
+const GROUP_ENV_FILE: &str = "group.env";
+
+const HOSTS_FILE: &str = "/etc/hosts";
+const HOSTS_BLOCK_BEGIN: &str = "# BEGIN instance-init group";
+const HOSTS_BLOCK_END: &str = "# END instance-init group";
+
+#[derive(Serialize)]
+struct GroupFile<'a> {
+    instance_group_id: &'a str,
+    size: usize,
+    // Position of this instance in `instances`, if it is part of them
+    index: Option<usize>,
+    leader: Option<&'a str>,
+    instances: Vec<&'a CloudInstance>,
+}
+
+// Members are sorted by instance id, so that every member sees the same order
+fn sorted_members(group: &CloudInstanceGroup) -> Vec<&CloudInstance> {
+    let mut members = group.instances.iter().collect::<Vec<_>>();
+    members.sort_by(|a, b| a.instance_id.cmp(&b.instance_id));
+    members
+}
+
+fn leader(group: &CloudInstanceGroup) -> Option<&str> {
+    group
+        .instances
+        .iter()
+        .find(|member| member.is_leader)
+        .map(|member| member.instance_id.as_str())
+}
+
+fn env_list<'a>(
+    members: &[&'a CloudInstance],
+    field: impl Fn(&'a CloudInstance) -> Option<&'a str>,
+) -> String {
+    members
+        .iter()
+        .map(|member| field(member).unwrap_or_default())
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+// Lists are comma separated and aligned: the n-th value of each one is about the same member
+fn env_file(
+    group: &CloudInstanceGroup,
+    members: &[&CloudInstance],
+    index: Option<usize>,
+) -> String {
+    let variables = [
+        ("INSTANCE_GROUP_ID", group.instance_group_id.clone()),
+        ("INSTANCE_GROUP_SIZE", group.size.to_string()),
+        (
+            "INSTANCE_GROUP_INDEX",
+            index.map(|index| index.to_string()).unwrap_or_default(),
+        ),
+        (
+            "INSTANCE_GROUP_LEADER",
+            leader(group).unwrap_or_default().to_string(),
+        ),
+        (
+            "INSTANCE_GROUP_IDS",
+            env_list(members, |member| Some(&member.instance_id)),
+        ),
+        (
+            "INSTANCE_GROUP_HOSTNAMES",
+            env_list(members, |member| Some(&member.hostname)),
+        ),
+        (
+            "INSTANCE_GROUP_IPV4",
+            env_list(members, |member| member.ipv4_address.as_deref()),
+        ),
+        (
+            "INSTANCE_GROUP_IPV6",
+            env_list(members, |member| member.ipv6_address.as_deref()),
+        ),
+    ];
+
+    variables
+        .iter()
+        .map(|(name, value)| format!("{}={}\n", name, value))
+        .collect()
+}
+
+pub fn write_group_files(
+    instance: &CloudInstance,
+    group: &CloudInstanceGroup,
+) -> Result<(), HostError> {
+    let members = sorted_members(group);
+    let index = members
+        .iter()
+        .position(|member| member.instance_id == instance.instance_id);
+
+    let directory = Path::new(RUNTIME_DIRECTORY);
+    fs::create_dir_all(directory)?;
+
+    let group_file = GroupFile {
+        instance_group_id: &group.instance_group_id,
+        size: group.size,
+        index,
+        leader: leader(group),
+        instances: members.clone(),
+    };
+    let json =
+        serde_json::to_string_pretty(&group_file).map_err(|err| HostError::IOError(err.into()))?;
+    fs::write(directory.join(GROUP_JSON_FILE), json)?;
+    fs::write(
+        directory.join(GROUP_ENV_FILE),
+        env_file(group, &members, index),
+    )?;
+    debug!("Instance group written to {}", directory.display());
+
+    Ok(())
+}
+
+// Replaces the managed block of the hosts file, or appends it when there is no
+// complete one, leaving the rest of the file untouched
+fn replace_hosts_block(hosts: &str, block: &str) -> String {
+    let lines = hosts.lines().collect::<Vec<_>>();
+    let begin = lines
+        .iter()
+        .position(|line| line.trim() == HOSTS_BLOCK_BEGIN);
+    let end = begin.and_then(|begin| {
+        lines[begin..]
+            .iter()
+            .position(|line| line.trim() == HOSTS_BLOCK_END)
+            .map(|end| begin + end)
+    });
+
+    let mut content = String::with_capacity(hosts.len() + block.len());
+    let (before, after) = match (begin, end) {
+        (Some(begin), Some(end)) => (&lines[..begin], &lines[end + 1..]),
+        _ => (&lines[..], &lines[lines.len()..]),
+    };
+    for line in before {
+        content.push_str(line);
+        content.push('\n');
+    }
+    content.push_str(block);
+    for line in after {
+        content.push_str(line);
+        content.push('\n');
+    }
+    content
+}
+
+// Written to a temporary file next to it, then renamed, so that the hosts file
+// is never seen partially written
+fn write_hosts_file(path: &Path, block: &str) -> Result<(), HostError> {
+    let hosts = match fs::read_to_string(path) {
+        Ok(hosts) => hosts,
+        Err(err) if err.kind() == ErrorKind::NotFound => String::new(),
+        Err(err) => return Err(err.into()),
+    };
+
+    let mut temporary = path.as_os_str().to_owned();
+    temporary.push(".instance-init");
+    let temporary = PathBuf::from(temporary);
+    fs::write(&temporary, replace_hosts_block(&hosts, block))?;
+    if let Ok(metadata) = fs::metadata(path) {
+        fs::set_permissions(&temporary, metadata.permissions())?;
+    }
+    fs::rename(&temporary, path)?;
+
+    Ok(())
+}
+
+pub fn write_group_hosts(group: &CloudInstanceGroup) -> Result<(), HostError> {
+    let mut block = format!("{}\n", HOSTS_BLOCK_BEGIN);
+    for member in sorted_members(group) {
+        for address in [&member.ipv4_address, &member.ipv6_address]
+            .into_iter()
+            .flatten()
+        {
+            block.push_str(&format!("{} {}\n", address, member.hostname));
+        }
+    }
+    block.push_str(&format!("{}\n", HOSTS_BLOCK_END));
+
+    write_hosts_file(Path::new(HOSTS_FILE), &block)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BLOCK: &str = "# BEGIN instance-init group\n10.0.0.1 a\n# END instance-init group\n";
+
+    #[test]
+    fn replaces_existing_block() {
+        let hosts = "127.0.0.1 localhost\n# BEGIN instance-init group\n10.0.0.9 old\n# END instance-init group\n::1 localhost\n";
+
+        assert_eq!(
+            replace_hosts_block(hosts, BLOCK),
+            format!("127.0.0.1 localhost\n{}::1 localhost\n", BLOCK)
+        );
+    }
+
+    #[test]
+    fn appends_block_without_end_marker() {
+        let hosts = "127.0.0.1 localhost\n# BEGIN instance-init group\n::1 localhost\n";
+
+        assert_eq!(
+            replace_hosts_block(hosts, BLOCK),
+            format!("{}{}", hosts, BLOCK)
+        );
+    }
+
+    #[test]
+    fn writes_missing_hosts_file() {
+        let path = std::env::temp_dir().join(format!("instance-init-{}-hosts", std::process::id()));
+        let _ = fs::remove_file(&path);
+
+        write_hosts_file(&path, BLOCK).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), BLOCK);
+
+        write_hosts_file(&path, BLOCK).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), BLOCK);
+
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,46 @@
+use crate::host::HostError;
+use crate::provider::CloudInstance;
+use log::error;
+use std::process::Command;
+
+// Hooks are shell commands run once the instance is configured, with a few
+// facts about the instance in their environment
+pub fn run_hook(command: &str, instance: &CloudInstance) -> Result<(), HostError> {
+    run_hook_with_env(command, instance, &[])
+}
+
+// Hooks called by a module (e.g. to publish an ACME challenge) get additional variables
+pub fn run_hook_with_env(
+    command: &str,
+    instance: &CloudInstance,
+    env: &[(&str, &str)],
+) -> Result<(), HostError> {
+    let mut cmd = Command::new("/bin/sh");
+    cmd.arg("-c")
+        .arg(command)
+        .env("INSTANCE_ID", &instance.instance_id)
+        .env(
+            "INSTANCE_ORDINAL",
+            instance
+                .ordinal
+                .map(|ordinal| ordinal.to_string())
+                .unwrap_or_default(),
+        )
+        .env("INSTANCE_IS_LEADER", instance.is_leader.to_string())
+        .envs(env.iter().copied());
+
+    let output = cmd.output().map_err(|err| {
+        error!("hook `{}` failed: {}", command, err);
+        HostError::HookError(command.to_string())
+    })?;
+
+    if !output.status.success() {
+        if let Ok(stderr) = String::from_utf8(output.stderr) {
+            error!("hook `{}` failed: {}", command, stderr);
+        }
+
+        return Err(HostError::HookError(command.to_string()));
+    }
+
+    Ok(())
+}
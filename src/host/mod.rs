@@ -1,9 +1,19 @@
 pub use crate::host::error::HostError;
+pub use crate::host::group::{write_group_files, write_group_hosts};
+pub use crate::host::hook::{run_hook, run_hook_with_env};
+pub use crate::host::network::{configure_networks, write_network_units};
+pub use crate::host::owner::set_owner;
+pub use crate::host::systemd::write_service_dropin;
 use log::error;
 use std::fs;
 use std::process::Command;
 
 mod error;
+mod group;
+mod hook;
+mod network;
+mod owner;
+mod systemd;
 
 pub fn set_instance_hostname(hostname: String) -> Result<(), HostError> {
     let mut cmd = Command::new("hostnamectl");
@@ -27,10 +37,12 @@ pub fn set_instance_hostname(hostname: String) -> Result<(), HostError> {
 
 pub fn ensure_ssh_hostkey(algorithm: &str) -> Result<(), HostError> {
     let mut cmd = Command::new("ssh-keygen");
-    cmd
-        .arg("-t").arg(algorithm)
-        .arg("-f").arg(format!("/var/lib/ssh/ssh_host_{}_key", algorithm))
-        .arg("-N").arg("");
+    cmd.arg("-t")
+        .arg(algorithm)
+        .arg("-f")
+        .arg(format!("/var/lib/ssh/ssh_host_{}_key", algorithm))
+        .arg("-N")
+        .arg("");
 
     let output = cmd.output().map_err(|err| {
         error!("ssh-keygen failed: {}", err);
@@ -0,0 +1,122 @@
+use crate::host::HostError;
+use crate::provider::CloudNetworkAttachment;
+use log::{error, info};
+use std::fs;
+use std::net::IpAddr;
+use std::path::Path;
+use std::process::Command;
+
+// Runtime units take precedence over the image ones and don't survive a reboot
+const NETWORKD_RUNTIME_DIRECTORY: &str = "/run/systemd/network";
+const NETWORKD_UNIT_PREFIX: &str = "10-instance-init";
+
+fn private_network_unit(
+    mac_address: &str,
+    address: Option<&str>,
+    prefix_length: Option<u8>,
+    dhcp: bool,
+) -> String {
+    let mut unit = format!("[Match]\nMACAddress={}\n\n[Network]\n", mac_address);
+
+    match (address, prefix_length) {
+        (Some(address), Some(prefix_length)) => {
+            unit.push_str(&format!("Address={}/{}\n", address, prefix_length))
+        }
+        _ if dhcp => unit.push_str("DHCP=ipv4\n"),
+        _ => unit.push_str("LinkLocalAddressing=no\n"),
+    }
+
+    unit
+}
+
+// Elastic IPs are routed to the instance, which must answer for them: they are
+// added to the loopback interface along its usual addresses
+fn loopback_unit(addresses: &[&str]) -> String {
+    let mut unit =
+        String::from("[Match]\nName=lo\n\n[Network]\nAddress=127.0.0.1/8\nAddress=::1/128\n");
+
+    for address in addresses {
+        let prefix_length = match address.parse::<IpAddr>() {
+            Ok(IpAddr::V6(_)) => 128,
+            _ => 32,
+        };
+        unit.push_str(&format!("Address={}/{}\n", address, prefix_length));
+    }
+
+    unit
+}
+
+fn reload_networkd() -> Result<(), HostError> {
+    let output = Command::new("networkctl")
+        .arg("reload")
+        .output()
+        .map_err(|err| {
+            error!("networkctl failed: {}", err);
+            HostError::NetworkSetupError
+        })?;
+
+    if !output.status.success() {
+        if let Ok(stderr) = String::from_utf8(output.stderr) {
+            error!("networkctl failed: {}", stderr);
+        }
+
+        return Err(HostError::NetworkSetupError);
+    }
+
+    Ok(())
+}
+
+pub fn configure_networks(networks: &[CloudNetworkAttachment]) -> Result<(), HostError> {
+    fs::create_dir_all(NETWORKD_RUNTIME_DIRECTORY)?;
+    let directory = Path::new(NETWORKD_RUNTIME_DIRECTORY);
+    let mut elastic_ips = Vec::new();
+
+    for network in networks {
+        match network {
+            CloudNetworkAttachment::PrivateNetwork {
+                id,
+                mac_address,
+                address,
+                prefix_length,
+                dhcp,
+                ..
+            } => {
+                info!("Configuring private network {} on {}", id, mac_address);
+                let unit =
+                    private_network_unit(mac_address, address.as_deref(), *prefix_length, *dhcp);
+                let name = format!(
+                    "{}-{}.network",
+                    NETWORKD_UNIT_PREFIX,
+                    mac_address.replace(':', "")
+                );
+                fs::write(directory.join(name), unit)?;
+            }
+            CloudNetworkAttachment::ElasticIp { address, .. } => {
+                info!("Configuring elastic IP {}", address);
+                elastic_ips.push(address.as_str());
+            }
+        }
+    }
+
+    if !elastic_ips.is_empty() {
+        let name = format!("{}-lo.network", NETWORKD_UNIT_PREFIX);
+        fs::write(directory.join(name), loopback_unit(&elastic_ips))?;
+    }
+
+    reload_networkd()
+}
+
+// Units of virtual interfaces, e.g. `wg0.netdev` and `wg0.network`, prefixed like the other ones
+pub fn write_network_units(units: &[(String, String)]) -> Result<(), HostError> {
+    fs::create_dir_all(NETWORKD_RUNTIME_DIRECTORY)?;
+    let directory = Path::new(NETWORKD_RUNTIME_DIRECTORY);
+
+    for (name, unit) in units {
+        fs::write(
+            directory.join(format!("{}-{}", NETWORKD_UNIT_PREFIX, name)),
+            unit,
+        )?;
+    }
+
+    reload_networkd()
+}
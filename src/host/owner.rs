@@ -0,0 +1,37 @@
+use crate::host::HostError;
+use std::fs;
+use std::os::unix::fs::chown;
+use std::path::Path;
+
+const PASSWD_FILE: &str = "/etc/passwd";
+const GROUP_FILE: &str = "/etc/group";
+
+// Looks up a name (or numeric id) in a passwd(5) or group(5) formatted file
+fn lookup_id(database: &str, name: &str) -> Result<u32, HostError> {
+    if let Ok(id) = name.parse::<u32>() {
+        return Ok(id);
+    }
+
+    fs::read_to_string(database)?
+        .lines()
+        .map(|line| line.split(':').collect::<Vec<_>>())
+        .find(|fields| fields.len() > 2 && fields[0] == name)
+        .and_then(|fields| fields[2].parse::<u32>().ok())
+        .ok_or_else(|| HostError::OwnerError(name.to_string()))
+}
+
+// Changes the owner of a file, given as "user" or "user:group"
+pub fn set_owner(path: &Path, owner: &str) -> Result<(), HostError> {
+    let (user, group) = match owner.split_once(':') {
+        Some((user, group)) => (user, Some(group)),
+        None => (owner, None),
+    };
+
+    let uid = lookup_id(PASSWD_FILE, user)?;
+    let gid = match group {
+        Some(group) => Some(lookup_id(GROUP_FILE, group)?),
+        None => None,
+    };
+
+    Ok(chown(path, Some(uid), gid)?)
+}
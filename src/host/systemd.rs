@@ -0,0 +1,37 @@
+use crate::host::HostError;
+use log::error;
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+const SYSTEMD_RUNTIME_DIRECTORY: &str = "/run/systemd/system";
+const DROPIN_FILE: &str = "10-instance-init.conf";
+
+fn daemon_reload() -> Result<(), HostError> {
+    let output = Command::new("systemctl")
+        .arg("daemon-reload")
+        .output()
+        .map_err(|err| {
+            error!("systemctl failed: {}", err);
+            HostError::ServiceSetupError
+        })?;
+
+    if !output.status.success() {
+        if let Ok(stderr) = String::from_utf8(output.stderr) {
+            error!("systemctl failed: {}", stderr);
+        }
+
+        return Err(HostError::ServiceSetupError);
+    }
+
+    Ok(())
+}
+
+// Runtime drop-ins are gone on reboot, they are written again on every boot
+pub fn write_service_dropin(service: &str, content: &str) -> Result<(), HostError> {
+    let directory = Path::new(SYSTEMD_RUNTIME_DIRECTORY).join(format!("{}.d", service));
+    fs::create_dir_all(&directory)?;
+    fs::write(directory.join(DROPIN_FILE), content)?;
+
+    daemon_reload()
+}
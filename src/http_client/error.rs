@@ -0,0 +1,245 @@
+use hyper::header::{InvalidHeaderName, InvalidHeaderValue};
+use hyper::StatusCode;
+use std::error::Error;
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+
+pub type ErrorSource = Arc<dyn Error + Send + Sync>;
+
+// Error bodies end up in logs: only their beginning is shown, with secrets masked
+const DISPLAYED_BODY_LIMIT: usize = 256;
+
+// Errors reach hyper wrapped in (possibly nested) I/O errors, which don't
+// expose the error they wrap as their source
+fn any_cause(
+    error: &(dyn Error + 'static),
+    predicate: &dyn Fn(&(dyn Error + 'static)) -> bool,
+) -> bool {
+    if predicate(error) {
+        return true;
+    }
+
+    let inner = error
+        .downcast_ref::<std::io::Error>()
+        .and_then(|error| error.get_ref())
+        .map(|inner| inner as &(dyn Error + 'static));
+
+    inner
+        .or_else(|| error.source())
+        .is_some_and(|cause| any_cause(cause, predicate))
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TimeoutPhase {
+    Connect,
+    Headers,
+    Body,
+}
+
+impl fmt::Display for TimeoutPhase {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TimeoutPhase::Connect => write!(f, "connecting"),
+            TimeoutPhase::Headers => write!(f, "waiting for response headers"),
+            TimeoutPhase::Body => write!(f, "reading response body"),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub enum HttpError {
+    Timeout {
+        url: String,
+        phase: TimeoutPhase,
+    },
+    TlsError {
+        url: String,
+        source: ErrorSource,
+    },
+    RequestError {
+        reason: String,
+    },
+    ConfigurationError {
+        reason: String,
+    },
+    TransportError {
+        url: String,
+        source: ErrorSource,
+    },
+    ResponseError {
+        url: String,
+        reason: String,
+    },
+    BodyTooLarge {
+        url: String,
+        limit: usize,
+    },
+    ChecksumMismatch {
+        url: String,
+        expected: String,
+        actual: String,
+    },
+    WriteError {
+        path: String,
+        source: ErrorSource,
+    },
+    ClientError {
+        url: String,
+        status: StatusCode,
+        body: String,
+    },
+    ServerError {
+        url: String,
+        status: StatusCode,
+        body: String,
+    },
+    TooManyRequests {
+        url: String,
+        retry_after: Option<Duration>,
+    },
+}
+
+impl HttpError {
+    // Transport errors are classified as TLS errors or connection timeouts from their source chain
+    pub fn from_transport(url: &str, error: hyper::Error) -> Self {
+        let source = match error.source() {
+            Some(source) => source,
+            None => {
+                return HttpError::TransportError {
+                    url: url.to_string(),
+                    source: Arc::new(error),
+                }
+            }
+        };
+
+        if any_cause(source, &|cause| cause.is::<rustls::Error>()) {
+            return HttpError::TlsError {
+                url: url.to_string(),
+                source: Arc::new(error),
+            };
+        }
+
+        let timed_out = any_cause(source, &|cause| {
+            cause
+                .downcast_ref::<std::io::Error>()
+                .is_some_and(|cause| cause.kind() == std::io::ErrorKind::TimedOut)
+        });
+        if error.is_connect() && timed_out {
+            return HttpError::Timeout {
+                url: url.to_string(),
+                phase: TimeoutPhase::Connect,
+            };
+        }
+
+        HttpError::TransportError {
+            url: url.to_string(),
+            source: Arc::new(error),
+        }
+    }
+
+    pub fn from_status(url: &str, status: StatusCode, body: String) -> Self {
+        let url = url.to_string();
+        match status.is_server_error() {
+            true => HttpError::ServerError { url, status, body },
+            false => HttpError::ClientError { url, status, body },
+        }
+    }
+}
+
+impl fmt::Display for HttpError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HttpError::Timeout { url, phase } => write!(f, "{}: timed out while {}", url, phase),
+            HttpError::TlsError { url, source } => write!(f, "{}: TLS error: {}", url, source),
+            HttpError::RequestError { reason } => write!(f, "invalid request: {}", reason),
+            HttpError::ConfigurationError { reason } => {
+                write!(f, "invalid client configuration: {}", reason)
+            }
+            HttpError::TransportError { url, source } => write!(f, "{}: {}", url, source),
+            HttpError::ResponseError { url, reason } => {
+                write!(f, "{}: invalid response: {}", url, reason)
+            }
+            HttpError::BodyTooLarge { url, limit } => {
+                write!(f, "{}: response body larger than {} bytes", url, limit)
+            }
+            HttpError::ChecksumMismatch {
+                url,
+                expected,
+                actual,
+            } => {
+                write!(f, "{}: sha256 is {}, expected {}", url, actual, expected)
+            }
+            HttpError::WriteError { path, source } => write!(f, "{}: {}", path, source),
+            HttpError::ClientError { url, status, body }
+            | HttpError::ServerError { url, status, body } => match body.is_empty() {
+                true => write!(f, "{}: {}", url, status),
+                false => write!(
+                    f,
+                    "{}: {}: {}",
+                    url,
+                    status,
+                    super::format_body(body.trim(), DISPLAYED_BODY_LIMIT)
+                ),
+            },
+            HttpError::TooManyRequests { url, .. } => {
+                write!(f, "{}: {}", url, StatusCode::TOO_MANY_REQUESTS)
+            }
+        }
+    }
+}
+
+impl Error for HttpError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            HttpError::TlsError { source, .. }
+            | HttpError::TransportError { source, .. }
+            | HttpError::WriteError { source, .. } => Some(source.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+impl From<hyper::http::Error> for HttpError {
+    fn from(error: hyper::http::Error) -> Self {
+        HttpError::RequestError {
+            reason: error.to_string(),
+        }
+    }
+}
+
+impl From<InvalidHeaderValue> for HttpError {
+    fn from(error: InvalidHeaderValue) -> Self {
+        HttpError::RequestError {
+            reason: error.to_string(),
+        }
+    }
+}
+
+impl From<InvalidHeaderName> for HttpError {
+    fn from(error: InvalidHeaderName) -> Self {
+        HttpError::RequestError {
+            reason: error.to_string(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::secret::Secret;
+
+    #[test]
+    fn displayed_body_is_truncated_and_redacted() {
+        let secret = Secret::new("error-body-secret".to_string());
+        let body = format!("{{\"token\": \"{}\"}}{}", secret.expose(), "x".repeat(1000));
+        let error = HttpError::from_status("https://example.net/", StatusCode::FORBIDDEN, body);
+
+        let displayed = error.to_string();
+        assert!(
+            displayed.starts_with("https://example.net/: 403 Forbidden: {\"token\": \"***\"}xxx")
+        );
+        assert!(displayed.ends_with("... (1030 bytes)"));
+        assert!(!displayed.contains(secret.expose()));
+    }
+}
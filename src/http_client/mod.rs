@@ -0,0 +1,533 @@
+mod error;
+mod proxy;
+mod request;
+mod retry;
+mod tls;
+
+pub use error::{HttpError, TimeoutPhase};
+pub use proxy::ProxyConfiguration;
+pub use request::{HttpRequest, HttpResponse};
+pub use retry::RetryPolicy;
+pub use tls::TlsConfiguration;
+
+use crate::http_client::proxy::{Proxies, ProxyConnector};
+use crate::secret;
+use futures::stream::{FuturesUnordered, StreamExt};
+use hyper::body::HttpBody;
+use hyper::client::HttpConnector;
+use hyper::header::{
+    AUTHORIZATION, CONTENT_LENGTH, COOKIE, PROXY_AUTHORIZATION, RETRY_AFTER, SET_COOKIE,
+};
+use hyper::http::response::Parts;
+use hyper::http::HeaderValue;
+use hyper::http::{HeaderMap, HeaderName};
+use hyper::{Body, Client, Request, StatusCode};
+use hyper_rustls::{HttpsConnector, HttpsConnectorBuilder};
+use log::{debug, log_enabled, warn, Level};
+use serde::de::DeserializeOwned;
+use sha2::{Digest, Sha256};
+use std::env;
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
+use tokio::fs::OpenOptions;
+use tokio::io::AsyncWriteExt;
+
+// Logging of bodies is opt-in: set to the maximum number of bytes to log
+const HTTP_LOG_BODY_ENV: &str = "INSTANCE_INIT_HTTP_LOG_BODY";
+
+// Delay before racing the next address, as recommended by RFC 8305
+const HAPPY_EYEBALLS_DELAY: Duration = Duration::from_millis(250);
+
+const DEFAULT_MAX_BODY_SIZE: usize = 16 * 1024 * 1024;
+
+// Downloads may hold secrets, only their owner may read them
+const DOWNLOAD_FILE_MODE: u32 = 0o600;
+
+const SENSITIVE_HEADERS: [HeaderName; 4] = [AUTHORIZATION, PROXY_AUTHORIZATION, COOKIE, SET_COOKIE];
+
+// Retry-After is either a number of seconds or an HTTP date
+fn parse_retry_after(headers: &HeaderMap) -> Option<Duration> {
+    let value = headers.get(RETRY_AFTER)?.to_str().ok()?;
+    match value.trim().parse::<u64>() {
+        Ok(seconds) => Some(Duration::from_secs(seconds)),
+        Err(_) => httpdate::parse_http_date(value)
+            .ok()?
+            .duration_since(SystemTime::now())
+            .ok(),
+    }
+}
+
+fn format_headers(headers: &HeaderMap) -> String {
+    headers
+        .iter()
+        .map(|(name, value)| {
+            let value = match SENSITIVE_HEADERS.contains(name) {
+                true => "***".to_string(),
+                false => secret::redact(value.to_str().unwrap_or("<binary>")),
+            };
+            format!("{}: {}", name, value)
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+fn format_body(body: &str, limit: usize) -> String {
+    let mut end = body.len().min(limit);
+    while !body.is_char_boundary(end) {
+        end -= 1;
+    }
+
+    let mut formatted = secret::redact(&body[..end]);
+    if end < body.len() {
+        formatted.push_str(&format!("... ({} bytes)", body.len()));
+    }
+    formatted
+}
+
+fn content_length(headers: &HeaderMap) -> Option<u64> {
+    headers.get(CONTENT_LENGTH)?.to_str().ok()?.parse().ok()
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+pub struct HttpClient {
+    client: Client<HttpsConnector<ProxyConnector>>,
+    timeout: Duration,
+    tls: Option<rustls::ClientConfig>,
+    proxies: Arc<Proxies>,
+    proxy_configuration: ProxyConfiguration,
+    metadata_endpoints: Vec<String>,
+    retry_policy: RetryPolicy,
+    max_body_size: usize,
+    body_log_limit: Option<usize>,
+}
+
+fn build_client(
+    timeout: Duration,
+    tls: Option<rustls::ClientConfig>,
+    proxies: Arc<Proxies>,
+) -> Client<HttpsConnector<ProxyConnector>> {
+    let mut http = HttpConnector::new();
+    http.enforce_http(false);
+    http.set_connect_timeout(Some(timeout));
+
+    let https = match tls {
+        Some(tls) => HttpsConnectorBuilder::new().with_tls_config(tls),
+        None => HttpsConnectorBuilder::new().with_native_roots(),
+    };
+    let https = https
+        .https_or_http()
+        .enable_all_versions()
+        .wrap_connector(ProxyConnector::new(http, proxies));
+
+    Client::builder().build::<_, Body>(https)
+}
+
+impl HttpClient {
+    pub fn new(timeout: u64) -> Self {
+        let timeout = Duration::from_secs(timeout);
+        let proxy_configuration = ProxyConfiguration::from_env();
+        let proxies = Arc::new(Proxies::new(&proxy_configuration, &[]));
+        let client = build_client(timeout, None, proxies.clone());
+
+        let body_log_limit = env::var(HTTP_LOG_BODY_ENV)
+            .ok()
+            .and_then(|limit| limit.parse().ok());
+
+        Self {
+            client,
+            timeout,
+            tls: None,
+            proxies,
+            proxy_configuration,
+            metadata_endpoints: Vec::new(),
+            retry_policy: RetryPolicy::default(),
+            max_body_size: DEFAULT_MAX_BODY_SIZE,
+            body_log_limit,
+        }
+    }
+
+    fn rebuild(&mut self) {
+        self.proxies = Arc::new(Proxies::new(
+            &self.proxy_configuration,
+            &self.metadata_endpoints,
+        ));
+        self.client = build_client(self.timeout, self.tls.clone(), self.proxies.clone());
+    }
+
+    pub fn set_timeout(&mut self, timeout: u64) {
+        self.timeout = Duration::from_secs(timeout);
+        self.rebuild();
+    }
+
+    pub fn set_tls(&mut self, configuration: &TlsConfiguration) -> Result<(), HttpError> {
+        self.tls = Some(tls::build_client_config(configuration)?);
+        self.rebuild();
+        Ok(())
+    }
+
+    // Proxies from the environment still apply to what the configuration leaves unset
+    pub fn set_proxy(&mut self, configuration: &ProxyConfiguration) {
+        self.proxy_configuration = configuration.or(&ProxyConfiguration::from_env());
+        self.rebuild();
+    }
+
+    // Metadata endpoints are never reached through a proxy
+    pub fn set_metadata_endpoints(&mut self, endpoints: &[String]) {
+        self.metadata_endpoints = endpoints.to_vec();
+        self.rebuild();
+    }
+
+    pub fn set_max_body_size(&mut self, max_body_size: usize) {
+        self.max_body_size = max_body_size;
+    }
+
+    pub fn set_retry_policy(&mut self, retry_policy: RetryPolicy) {
+        self.retry_policy = retry_policy;
+    }
+
+    fn log_request(&self, req: &Request<Body>) {
+        if log_enabled!(Level::Debug) {
+            debug!(
+                "HTTP req: {} {} [{}]",
+                req.method(),
+                secret::redact(&req.uri().to_string()),
+                format_headers(req.headers())
+            );
+        }
+    }
+
+    fn log_body(&self, body: &str) {
+        if let Some(limit) = self.body_log_limit {
+            debug!("HTTP body: {}", format_body(body, limit));
+        }
+    }
+
+    // Sends the request and waits for the response headers
+    async fn execute(&self, mut req: Request<Body>) -> Result<(Parts, Body), HttpError> {
+        if let Some(authorization) = self.proxies.authorization_for(req.uri()) {
+            req.headers_mut()
+                .insert(PROXY_AUTHORIZATION, HeaderValue::from_str(authorization)?);
+        }
+
+        self.log_request(&req);
+        let url = req.uri().to_string();
+
+        let res = self.client.request(req);
+        let res = tokio::time::timeout(self.timeout, res)
+            .await
+            .map_err(|_| HttpError::Timeout {
+                url: url.clone(),
+                phase: TimeoutPhase::Headers,
+            })?
+            .map_err(|err| HttpError::from_transport(&url, err))?;
+
+        if log_enabled!(Level::Debug) {
+            debug!(
+                "HTTP res: {} [{}]",
+                res.status(),
+                format_headers(res.headers())
+            );
+        }
+
+        let (parts, body) = res.into_parts();
+        if let Some(length) = content_length(&parts.headers) {
+            if length > self.max_body_size as u64 {
+                return Err(HttpError::BodyTooLarge {
+                    url,
+                    limit: self.max_body_size,
+                });
+            }
+        }
+
+        Ok((parts, body))
+    }
+
+    async fn read_body(
+        &self,
+        url: &str,
+        mut body: Body,
+        logged: bool,
+    ) -> Result<String, HttpError> {
+        let read = async {
+            let mut content = Vec::new();
+            while let Some(chunk) = body.data().await {
+                let chunk = chunk.map_err(|err| HttpError::from_transport(url, err))?;
+                if content.len() + chunk.len() > self.max_body_size {
+                    return Err(HttpError::BodyTooLarge {
+                        url: url.to_string(),
+                        limit: self.max_body_size,
+                    });
+                }
+                content.extend_from_slice(&chunk);
+            }
+            Ok(content)
+        };
+
+        let content = tokio::time::timeout(self.timeout, read)
+            .await
+            .map_err(|_| HttpError::Timeout {
+                url: url.to_string(),
+                phase: TimeoutPhase::Body,
+            })??;
+        let body = String::from_utf8(content).map_err(|err| HttpError::ResponseError {
+            url: url.to_string(),
+            reason: err.to_string(),
+        })?;
+
+        if logged {
+            self.log_body(&body);
+        }
+        Ok(body)
+    }
+
+    // Bodies of sensitive requests are never logged, their secrets may not be
+    // registered for redaction yet
+    async fn parse_response(
+        &self,
+        req: Request<Body>,
+        sensitive: bool,
+    ) -> Result<HttpResponse, HttpError> {
+        let url = req.uri().to_string();
+        let (parts, body) = self.execute(req).await?;
+
+        let response = HttpResponse {
+            body: self.read_body(&url, body, !sensitive).await?,
+            url,
+            status: parts.status,
+            headers: parts.headers,
+        };
+
+        if response.status == StatusCode::TOO_MANY_REQUESTS {
+            return Err(HttpError::TooManyRequests {
+                retry_after: parse_retry_after(&response.headers),
+                url: response.url,
+            });
+        } else if response.status.is_client_error() || response.status.is_server_error() {
+            return Err(HttpError::from_status(
+                &response.url,
+                response.status,
+                response.body,
+            ));
+        }
+
+        Ok(response)
+    }
+
+    // Streams the body to `path` through a temporary file, hashing it on the fly.
+    // The file is only moved in place once complete and matching `sha256`.
+    async fn download_response(
+        &self,
+        req: Request<Body>,
+        path: &Path,
+        sha256: Option<&str>,
+    ) -> Result<u64, HttpError> {
+        let url = req.uri().to_string();
+        let (parts, mut body) = self.execute(req).await?;
+        if !parts.status.is_success() {
+            let response = HttpResponse {
+                body: self.read_body(&url, body, true).await?,
+                url,
+                status: parts.status,
+                headers: parts.headers,
+            };
+            return Err(match response.status {
+                StatusCode::TOO_MANY_REQUESTS => HttpError::TooManyRequests {
+                    retry_after: parse_retry_after(&response.headers),
+                    url: response.url,
+                },
+                status => HttpError::from_status(&response.url, status, response.body),
+            });
+        }
+
+        let mut partial = path.as_os_str().to_owned();
+        partial.push(".part");
+        let partial = PathBuf::from(partial);
+        let write_error = |err: std::io::Error| HttpError::WriteError {
+            path: partial.display().to_string(),
+            source: Arc::new(err),
+        };
+
+        let mut file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .mode(DOWNLOAD_FILE_MODE)
+            .open(&partial)
+            .await
+            .map_err(write_error)?;
+        let mut hasher = Sha256::new();
+        let mut size = 0;
+
+        let result = async {
+            loop {
+                // Large downloads are bounded by the time between chunks rather than in total
+                let chunk = tokio::time::timeout(self.timeout, body.data())
+                    .await
+                    .map_err(|_| HttpError::Timeout {
+                        url: url.clone(),
+                        phase: TimeoutPhase::Body,
+                    })?;
+                let chunk = match chunk {
+                    Some(chunk) => chunk.map_err(|err| HttpError::from_transport(&url, err))?,
+                    None => break,
+                };
+
+                size += chunk.len();
+                if size > self.max_body_size {
+                    return Err(HttpError::BodyTooLarge {
+                        url: url.clone(),
+                        limit: self.max_body_size,
+                    });
+                }
+                hasher.update(&chunk);
+                file.write_all(&chunk).await.map_err(write_error)?;
+            }
+            file.sync_all().await.map_err(write_error)?;
+
+            let actual = hex(&hasher.finalize());
+            if let Some(expected) = sha256 {
+                if !actual.eq_ignore_ascii_case(expected) {
+                    return Err(HttpError::ChecksumMismatch {
+                        url: url.clone(),
+                        expected: expected.to_string(),
+                        actual,
+                    });
+                }
+            }
+            debug!(
+                "HTTP download: {} bytes with sha256 {} to {}",
+                size,
+                actual,
+                path.display()
+            );
+
+            tokio::fs::rename(&partial, path).await.map_err(write_error)
+        }
+        .await;
+
+        if result.is_err() {
+            let _ = tokio::fs::remove_file(&partial).await;
+        }
+        result.map(|_| size as u64)
+    }
+
+    // Sends the request again for every attempt allowed by the retry policy.
+    // Requests that aren't idempotent are only retried when rate limited.
+    async fn with_retries<'a, T, F, Fut>(
+        &'a self,
+        request: &HttpRequest,
+        attempt_fn: F,
+    ) -> Result<T, HttpError>
+    where
+        F: Fn(Request<Body>) -> Fut,
+        Fut: Future<Output = Result<T, HttpError>> + 'a,
+    {
+        let policy = &self.retry_policy;
+        let deadline = policy.deadline.map(|deadline| Instant::now() + deadline);
+        let mut attempt = 1;
+
+        loop {
+            let error = match attempt_fn(request.build()?).await {
+                Ok(response) => return Ok(response),
+                Err(error) => error,
+            };
+
+            let retryable = match request.is_idempotent() {
+                true => policy.is_retryable(&error),
+                false => matches!(error, HttpError::TooManyRequests { .. }),
+            };
+            if attempt >= policy.max_attempts || !retryable {
+                return Err(error);
+            }
+
+            let delay = policy.delay(attempt, &error);
+            if deadline.is_some_and(|deadline| Instant::now() + delay >= deadline) {
+                warn!("HTTP request deadline exceeded after {} attempts", attempt);
+                return Err(error);
+            }
+
+            warn!(
+                "HTTP {} failed ({}), retrying in {}ms (attempt {}/{})",
+                request.method(),
+                error,
+                delay.as_millis(),
+                attempt,
+                policy.max_attempts
+            );
+            tokio::time::sleep(delay).await;
+            attempt += 1;
+        }
+    }
+
+    pub async fn send(&self, request: HttpRequest) -> Result<HttpResponse, HttpError> {
+        let sensitive = request.is_sensitive();
+        self.with_retries(&request, |req| self.parse_response(req, sensitive))
+            .await
+    }
+
+    pub async fn download(
+        &self,
+        request: HttpRequest,
+        path: &Path,
+        sha256: Option<&str>,
+    ) -> Result<u64, HttpError> {
+        self.with_retries(&request, |req| self.download_response(req, path, sha256))
+            .await
+    }
+
+    // Races equivalent requests (e.g. the IPv4 and IPv6 addresses of a service),
+    // starting the next one whenever the previous fails or is still pending after
+    // a short delay, and returns the first successful response.
+    pub async fn send_any(&self, requests: Vec<HttpRequest>) -> Result<HttpResponse, HttpError> {
+        let mut pending = requests.into_iter();
+        let mut racing = FuturesUnordered::new();
+        let mut last_error = None;
+
+        match pending.next() {
+            Some(request) => racing.push(self.send(request)),
+            None => {
+                return Err(HttpError::RequestError {
+                    reason: "no request to send".to_string(),
+                })
+            }
+        }
+
+        loop {
+            let started = pending.len() == 0;
+            tokio::select! {
+                result = racing.next(), if !racing.is_empty() => match result {
+                    Some(Ok(response)) => return Ok(response),
+                    Some(Err(error)) => {
+                        debug!("HTTP race attempt failed: {}", error);
+                        last_error = Some(error);
+                        if let Some(request) = pending.next() {
+                            racing.push(self.send(request));
+                        }
+                    }
+                    None => {}
+                },
+                _ = tokio::time::sleep(HAPPY_EYEBALLS_DELAY), if !started => {
+                    if let Some(request) = pending.next() {
+                        racing.push(self.send(request));
+                    }
+                }
+            }
+
+            if racing.is_empty() && pending.len() == 0 {
+                return Err(last_error.expect("a failed request"));
+            }
+        }
+    }
+
+    pub async fn send_json<T: DeserializeOwned>(
+        &self,
+        request: HttpRequest,
+    ) -> Result<T, HttpError> {
+        self.send(request).await?.json()
+    }
+}
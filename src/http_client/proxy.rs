@@ -0,0 +1,418 @@
+use hyper::client::connect::{Connected, Connection};
+use hyper::client::HttpConnector;
+use hyper::service::Service;
+use hyper::Uri;
+use log::debug;
+use serde::Deserialize;
+use std::env;
+use std::error::Error;
+use std::future::Future;
+use std::io;
+use std::net::IpAddr;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, ReadBuf};
+use tokio::net::TcpStream;
+
+type BoxError = Box<dyn Error + Send + Sync>;
+
+const CONNECT_RESPONSE_MAX_SIZE: usize = 8192;
+
+#[derive(Clone, Deserialize, Debug, Default)]
+pub struct ProxyConfiguration {
+    pub http_proxy: Option<String>,
+    pub https_proxy: Option<String>,
+    // Hosts, domain suffixes, IP addresses or CIDR ranges reached directly ("*" for all)
+    #[serde(default)]
+    pub no_proxy: Vec<String>,
+}
+
+fn env_var(name: &str) -> Option<String> {
+    env::var(name.to_lowercase())
+        .or_else(|_| env::var(name))
+        .ok()
+        .filter(|value| !value.is_empty())
+}
+
+impl ProxyConfiguration {
+    pub fn from_env() -> Self {
+        Self {
+            http_proxy: env_var("HTTP_PROXY"),
+            https_proxy: env_var("HTTPS_PROXY"),
+            no_proxy: env_var("NO_PROXY")
+                .map(|no_proxy| {
+                    no_proxy
+                        .split(',')
+                        .map(|rule| rule.trim().to_string())
+                        .collect()
+                })
+                .unwrap_or_default(),
+        }
+    }
+
+    // Configured values take precedence over the environment
+    pub fn or(&self, fallback: &ProxyConfiguration) -> ProxyConfiguration {
+        ProxyConfiguration {
+            http_proxy: self
+                .http_proxy
+                .clone()
+                .or_else(|| fallback.http_proxy.clone()),
+            https_proxy: self
+                .https_proxy
+                .clone()
+                .or_else(|| fallback.https_proxy.clone()),
+            no_proxy: match self.no_proxy.is_empty() {
+                true => fallback.no_proxy.clone(),
+                false => self.no_proxy.clone(),
+            },
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+struct Proxy {
+    uri: Uri,
+    authorization: Option<String>,
+}
+
+impl Proxy {
+    fn parse(proxy: &str) -> Option<Self> {
+        let proxy = match proxy.contains("://") {
+            true => proxy.to_string(),
+            false => format!("http://{}", proxy),
+        };
+        let uri: Uri = proxy.parse().ok()?;
+        if uri.scheme_str() != Some("http") {
+            debug!("Ignoring unsupported proxy {}", uri);
+            return None;
+        }
+
+        let authority = uri.authority()?.as_str();
+        let authorization = authority
+            .rsplit_once('@')
+            .map(|(credentials, _)| format!("Basic {}", base64::encode(credentials)));
+
+        Some(Self { uri, authorization })
+    }
+}
+
+// Metadata services are always reached directly, whether on link-local
+// addresses or on the configured metadata endpoints
+fn is_metadata_address(ip: &IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => ip.is_link_local() || ip.is_loopback(),
+        IpAddr::V6(ip) => (ip.segments()[0] & 0xffc0) == 0xfe80 || ip.is_loopback(),
+    }
+}
+
+fn normalize_host(host: &str) -> String {
+    host.trim_start_matches('[')
+        .trim_end_matches(']')
+        .to_lowercase()
+}
+
+fn in_cidr(ip: &IpAddr, cidr: &str) -> bool {
+    let (network, prefix) = match cidr.split_once('/') {
+        Some((network, prefix)) => (network, prefix),
+        None => return false,
+    };
+    let (network, prefix) = match (network.parse::<IpAddr>(), prefix.parse::<u32>()) {
+        (Ok(network), Ok(prefix)) => (network, prefix),
+        _ => return false,
+    };
+
+    match (ip, network) {
+        (IpAddr::V4(ip), IpAddr::V4(network)) if prefix <= 32 => {
+            let mask = u32::MAX.checked_shl(32 - prefix).unwrap_or(0);
+            u32::from(*ip) & mask == u32::from(network) & mask
+        }
+        (IpAddr::V6(ip), IpAddr::V6(network)) if prefix <= 128 => {
+            let mask = u128::MAX.checked_shl(128 - prefix).unwrap_or(0);
+            u128::from(*ip) & mask == u128::from(network) & mask
+        }
+        _ => false,
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Proxies {
+    http: Option<Proxy>,
+    https: Option<Proxy>,
+    no_proxy: Vec<String>,
+    metadata_hosts: Vec<String>,
+}
+
+impl Proxies {
+    pub fn new(configuration: &ProxyConfiguration, metadata_endpoints: &[String]) -> Self {
+        Self {
+            http: configuration.http_proxy.as_deref().and_then(Proxy::parse),
+            https: configuration.https_proxy.as_deref().and_then(Proxy::parse),
+            no_proxy: configuration
+                .no_proxy
+                .iter()
+                .map(|rule| rule.trim().to_lowercase())
+                .filter(|rule| !rule.is_empty())
+                .collect(),
+            metadata_hosts: metadata_endpoints
+                .iter()
+                .filter_map(|endpoint| endpoint.parse::<Uri>().ok())
+                .filter_map(|uri| uri.host().map(normalize_host))
+                .collect(),
+        }
+    }
+
+    fn bypass(&self, host: &str) -> bool {
+        let host = normalize_host(host);
+        if self.metadata_hosts.contains(&host) {
+            return true;
+        }
+
+        if let Ok(ip) = host.parse::<IpAddr>() {
+            if is_metadata_address(&ip) {
+                return true;
+            }
+            return self
+                .no_proxy
+                .iter()
+                .any(|rule| rule == "*" || *rule == host || in_cidr(&ip, rule));
+        }
+
+        self.no_proxy.iter().any(|rule| {
+            let domain = rule.trim_start_matches('.');
+            rule == "*" || host == domain || host.ends_with(&format!(".{}", domain))
+        })
+    }
+
+    fn proxy_for(&self, uri: &Uri) -> Option<&Proxy> {
+        let proxy = match uri.scheme_str() {
+            Some("https") => self.https.as_ref(),
+            _ => self.http.as_ref(),
+        }?;
+
+        match self.bypass(uri.host()?) {
+            true => None,
+            false => Some(proxy),
+        }
+    }
+
+    // Plain HTTP requests are forwarded by the proxy and need the credentials in their headers
+    pub fn authorization_for(&self, uri: &Uri) -> Option<&str> {
+        match uri.scheme_str() {
+            Some("https") => None,
+            _ => self.proxy_for(uri)?.authorization.as_deref(),
+        }
+    }
+}
+
+pub struct ProxyStream {
+    stream: TcpStream,
+    forwarded: bool,
+}
+
+impl Connection for ProxyStream {
+    fn connected(&self) -> Connected {
+        // Forwarded requests are sent in absolute-form to the proxy
+        self.stream.connected().proxy(self.forwarded)
+    }
+}
+
+impl AsyncRead for ProxyStream {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.stream).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for ProxyStream {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.stream).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.stream).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.stream).poll_shutdown(cx)
+    }
+}
+
+async fn tunnel(stream: &mut TcpStream, dst: &Uri, proxy: &Proxy) -> Result<(), BoxError> {
+    let host = dst.host().ok_or("missing destination host")?;
+    let port = dst.port_u16().unwrap_or(443);
+
+    let mut request = format!(
+        "CONNECT {host}:{port} HTTP/1.1\r\nHost: {host}:{port}\r\n",
+        host = host,
+        port = port
+    );
+    if let Some(authorization) = &proxy.authorization {
+        request.push_str(&format!("Proxy-Authorization: {}\r\n", authorization));
+    }
+    request.push_str("\r\n");
+    stream.write_all(request.as_bytes()).await?;
+
+    // The response is read byte by byte not to consume the beginning of the TLS handshake
+    let mut response = Vec::new();
+    while !response.ends_with(b"\r\n\r\n") {
+        if response.len() >= CONNECT_RESPONSE_MAX_SIZE {
+            return Err("proxy CONNECT response too large".into());
+        }
+        response.push(stream.read_u8().await?);
+    }
+
+    let status_line = String::from_utf8_lossy(&response);
+    let status_line = status_line.lines().next().unwrap_or_default();
+    match status_line.split_whitespace().nth(1) {
+        Some(status) if status.starts_with('2') => Ok(()),
+        _ => Err(format!("proxy refused tunnel: {}", status_line).into()),
+    }
+}
+
+#[derive(Clone)]
+pub struct ProxyConnector {
+    http: HttpConnector,
+    proxies: Arc<Proxies>,
+}
+
+impl ProxyConnector {
+    pub fn new(http: HttpConnector, proxies: Arc<Proxies>) -> Self {
+        Self { http, proxies }
+    }
+}
+
+impl Service<Uri> for ProxyConnector {
+    type Response = ProxyStream;
+    type Error = BoxError;
+    type Future = Pin<Box<dyn Future<Output = Result<ProxyStream, BoxError>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.http.poll_ready(cx).map_err(Into::into)
+    }
+
+    fn call(&mut self, dst: Uri) -> Self::Future {
+        let mut http = self.http.clone();
+        let proxy = self.proxies.proxy_for(&dst).cloned();
+
+        Box::pin(async move {
+            let proxy = match proxy {
+                Some(proxy) => proxy,
+                None => {
+                    return Ok(ProxyStream {
+                        stream: http.call(dst).await?,
+                        forwarded: false,
+                    })
+                }
+            };
+
+            debug!(
+                "Connecting to {} through proxy {}",
+                dst,
+                proxy.uri.host().unwrap_or_default()
+            );
+            let mut stream = http.call(proxy.uri.clone()).await?;
+
+            if dst.scheme_str() == Some("https") {
+                tunnel(&mut stream, &dst, &proxy).await?;
+                return Ok(ProxyStream {
+                    stream,
+                    forwarded: false,
+                });
+            }
+
+            Ok(ProxyStream {
+                stream,
+                forwarded: true,
+            })
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn proxies(no_proxy: &[&str], metadata_endpoints: &[&str]) -> Proxies {
+        Proxies::new(
+            &ProxyConfiguration {
+                http_proxy: Some("http://proxy.example.com:3128".to_string()),
+                https_proxy: Some("http://proxy.example.com:3128".to_string()),
+                no_proxy: no_proxy.iter().map(|rule| rule.to_string()).collect(),
+            },
+            &metadata_endpoints
+                .iter()
+                .map(|endpoint| endpoint.to_string())
+                .collect::<Vec<_>>(),
+        )
+    }
+
+    #[test]
+    fn metadata_endpoints_are_reached_directly() {
+        let proxies = proxies(
+            &[],
+            &["http://[FD00:EC2::254]", "http://metadata.internal/"],
+        );
+
+        assert!(proxies.bypass("[fd00:ec2::254]"));
+        assert!(proxies.bypass("metadata.internal"));
+        assert!(proxies.bypass("169.254.169.254"));
+        assert!(proxies.bypass("[fe80::1]"));
+        assert!(!proxies.bypass("[fd00:ec2::253]"));
+        assert!(!proxies.bypass("api.exoscale.com"));
+    }
+
+    #[test]
+    fn matches_cidr_ranges() {
+        let ipv4: IpAddr = "10.1.2.3".parse().unwrap();
+        assert!(in_cidr(&ipv4, "10.0.0.0/8"));
+        assert!(in_cidr(&ipv4, "10.1.2.3/32"));
+        assert!(in_cidr(&ipv4, "0.0.0.0/0"));
+        assert!(!in_cidr(&ipv4, "10.1.3.0/24"));
+        assert!(!in_cidr(&ipv4, "10.0.0.0/33"));
+        assert!(!in_cidr(&ipv4, "10.0.0.0"));
+        assert!(!in_cidr(&ipv4, "::/0"));
+
+        let ipv6: IpAddr = "2001:db8::1".parse().unwrap();
+        assert!(in_cidr(&ipv6, "2001:db8::/32"));
+        assert!(in_cidr(&ipv6, "::/0"));
+        assert!(!in_cidr(&ipv6, "2001:db9::/32"));
+        assert!(!in_cidr(&ipv6, "2001:db8::/129"));
+    }
+
+    #[test]
+    fn applies_no_proxy_rules() {
+        assert!(proxies(&["*"], &[]).bypass("api.exoscale.com"));
+
+        let proxies = proxies(
+            &["Example.com", ".internal", "10.0.0.0/8", "2001:db8::1"],
+            &[],
+        );
+
+        assert!(proxies.bypass("example.com"));
+        assert!(proxies.bypass("api.example.com"));
+        assert!(proxies.bypass("vault.internal"));
+        assert!(proxies.bypass("10.1.2.3"));
+        assert!(proxies.bypass("[2001:db8::1]"));
+        assert!(!proxies.bypass("notexample.com"));
+        assert!(!proxies.bypass("11.0.0.1"));
+        assert!(!proxies.bypass("[2001:db8::2]"));
+    }
+
+    #[test]
+    fn bypassed_hosts_get_no_proxy() {
+        let proxies = proxies(&["example.com"], &[]);
+
+        let direct: Uri = "http://example.com/".parse().unwrap();
+        let proxied: Uri = "https://api.exoscale.com/v2/instance".parse().unwrap();
+        assert!(proxies.proxy_for(&direct).is_none());
+        assert!(proxies.proxy_for(&proxied).is_some());
+    }
+}
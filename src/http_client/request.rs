@@ -0,0 +1,148 @@
+use crate::http_client::HttpError;
+use hyper::http::{HeaderMap, HeaderName, HeaderValue};
+use hyper::{Body, Method, Request, StatusCode};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::str::FromStr;
+
+const CONTENT_TYPE_JSON: &str = "application/json";
+
+// RFC 3986 unreserved characters are kept as-is, everything else is escaped
+fn percent_encode(value: &str) -> String {
+    value
+        .bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                (byte as char).to_string()
+            }
+            _ => format!("%{:02X}", byte),
+        })
+        .collect()
+}
+
+#[derive(Clone, Debug)]
+pub struct HttpRequest {
+    method: Method,
+    uri: String,
+    query: Vec<(String, String)>,
+    headers: Vec<(String, String)>,
+    body: Option<String>,
+    sensitive: bool,
+}
+
+impl HttpRequest {
+    pub fn new(method: Method, uri: String) -> Self {
+        Self {
+            method,
+            uri,
+            query: Vec::new(),
+            headers: Vec::new(),
+            body: None,
+            sensitive: false,
+        }
+    }
+
+    pub fn get(uri: String) -> Self {
+        Self::new(Method::GET, uri)
+    }
+
+    pub fn query(mut self, key: &str, value: &str) -> Self {
+        self.query.push((key.to_string(), value.to_string()));
+        self
+    }
+
+    pub fn header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    pub fn body(mut self, body: String) -> Self {
+        self.body = Some(body);
+        self
+    }
+
+    // The response carries secrets (e.g. tokens or private keys), its body isn't logged
+    pub fn sensitive(mut self) -> Self {
+        self.sensitive = true;
+        self
+    }
+
+    pub fn json<T: Serialize>(self, body: &T) -> Result<Self, HttpError> {
+        let body = serde_json::to_string(body).map_err(|err| HttpError::RequestError {
+            reason: err.to_string(),
+        })?;
+
+        Ok(self.header("content-type", CONTENT_TYPE_JSON).body(body))
+    }
+
+    pub fn method(&self) -> &Method {
+        &self.method
+    }
+
+    pub fn is_sensitive(&self) -> bool {
+        self.sensitive
+    }
+
+    pub fn payload(&self) -> Option<&str> {
+        self.body.as_deref()
+    }
+
+    // Safe to send again when a previous attempt may have reached the server
+    pub fn is_idempotent(&self) -> bool {
+        matches!(
+            self.method,
+            Method::GET | Method::HEAD | Method::PUT | Method::DELETE | Method::OPTIONS
+        )
+    }
+
+    pub fn uri(&self) -> String {
+        if self.query.is_empty() {
+            return self.uri.clone();
+        }
+
+        let query = self
+            .query
+            .iter()
+            .map(|(key, value)| format!("{}={}", percent_encode(key), percent_encode(value)))
+            .collect::<Vec<_>>()
+            .join("&");
+
+        format!("{}?{}", self.uri, query)
+    }
+
+    pub fn build(&self) -> Result<Request<Body>, HttpError> {
+        let body = match &self.body {
+            Some(body) => Body::from(body.clone()),
+            None => Body::empty(),
+        };
+
+        let mut req = Request::builder()
+            .method(self.method.clone())
+            .uri(self.uri())
+            .body(body)?;
+
+        for (name, value) in &self.headers {
+            req.headers_mut()
+                .insert(HeaderName::from_str(name)?, HeaderValue::from_str(value)?);
+        }
+
+        Ok(req)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct HttpResponse {
+    pub url: String,
+    pub status: StatusCode,
+    pub headers: HeaderMap,
+    pub body: String,
+}
+
+impl HttpResponse {
+    pub fn json<T: DeserializeOwned>(&self) -> Result<T, HttpError> {
+        serde_json::from_str(&self.body).map_err(|err| HttpError::ResponseError {
+            url: self.url.clone(),
+            reason: err.to_string(),
+        })
+    }
+}
@@ -0,0 +1,117 @@
+use crate::http_client::HttpError;
+use rand::Rng;
+use std::time::Duration;
+
+#[derive(Clone, Debug)]
+pub struct RetryPolicy {
+    pub max_attempts: u32,
+    pub initial_delay: Duration,
+    pub max_delay: Duration,
+    // Overall time budget for all attempts, delays included
+    pub deadline: Option<Duration>,
+}
+
+impl RetryPolicy {
+    pub fn new(max_attempts: u32, initial_delay: Duration, max_delay: Duration) -> Self {
+        Self {
+            max_attempts: max_attempts.max(1),
+            initial_delay,
+            max_delay,
+            deadline: None,
+        }
+    }
+
+    pub fn with_deadline(mut self, deadline: Duration) -> Self {
+        self.deadline = Some(deadline);
+        self
+    }
+
+    // Exponential backoff with full jitter, unless the server asked for a delay
+    pub fn delay(&self, attempt: u32, error: &HttpError) -> Duration {
+        if let HttpError::TooManyRequests {
+            retry_after: Some(retry_after),
+            ..
+        } = error
+        {
+            return (*retry_after).min(self.max_delay);
+        }
+
+        let backoff = self
+            .initial_delay
+            .saturating_mul(2u32.saturating_pow(attempt.saturating_sub(1)))
+            .min(self.max_delay);
+
+        rand::thread_rng().gen_range(Duration::ZERO..=backoff)
+    }
+
+    pub fn is_retryable(&self, error: &HttpError) -> bool {
+        matches!(
+            error,
+            HttpError::Timeout { .. }
+                | HttpError::TransportError { .. }
+                | HttpError::ServerError { .. }
+                | HttpError::TooManyRequests { .. }
+        )
+    }
+}
+
+impl Default for RetryPolicy {
+    // A single attempt
+    fn default() -> Self {
+        Self::new(1, Duration::ZERO, Duration::ZERO)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http_client::TimeoutPhase;
+
+    fn policy() -> RetryPolicy {
+        RetryPolicy::new(5, Duration::from_millis(100), Duration::from_secs(1))
+    }
+
+    fn too_many_requests(retry_after: Option<Duration>) -> HttpError {
+        HttpError::TooManyRequests {
+            url: "http://localhost/".to_string(),
+            retry_after,
+        }
+    }
+
+    #[test]
+    fn retry_after_is_clamped_to_max_delay() {
+        let policy = policy();
+
+        let delay = policy.delay(1, &too_many_requests(Some(Duration::from_millis(300))));
+        assert_eq!(delay, Duration::from_millis(300));
+        let delay = policy.delay(1, &too_many_requests(Some(Duration::from_secs(60))));
+        assert_eq!(delay, Duration::from_secs(1));
+
+        // Without Retry-After, the usual backoff applies
+        let delay = policy.delay(1, &too_many_requests(None));
+        assert!(delay <= Duration::from_millis(100));
+    }
+
+    #[test]
+    fn backoff_grows_up_to_max_delay() {
+        let policy = policy();
+        let error = HttpError::Timeout {
+            url: "http://localhost/".to_string(),
+            phase: TimeoutPhase::Connect,
+        };
+        let longest = |attempt| {
+            (0..200)
+                .map(|_| policy.delay(attempt, &error))
+                .max()
+                .unwrap()
+        };
+
+        // Jittered delays stay below the backoff, and almost surely exceed
+        // the previous one's
+        assert!(longest(1) <= Duration::from_millis(100));
+        let third = longest(3);
+        assert!(third > Duration::from_millis(200) && third <= Duration::from_millis(400));
+        assert!(longest(10) <= Duration::from_secs(1));
+        assert!(longest(u32::MAX) <= Duration::from_secs(1));
+    }
+}
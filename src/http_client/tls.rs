@@ -0,0 +1,160 @@
+use crate::http_client::HttpError;
+use log::{debug, warn};
+use rustls::client::{ServerCertVerified, ServerCertVerifier, WebPkiVerifier};
+use rustls::{Certificate, ClientConfig, OwnedTrustAnchor, PrivateKey, RootCertStore, ServerName};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::fs::File;
+use std::io::BufReader;
+use std::sync::Arc;
+use std::time::SystemTime;
+use x509_parser::prelude::{FromDer, X509Certificate};
+
+#[derive(Clone, Deserialize, Debug, Default)]
+pub struct TlsConfiguration {
+    // PEM bundles trusted in addition to the system roots
+    #[serde(default)]
+    pub ca_bundles: Vec<String>,
+    // PEM certificate chain and key for mutual TLS
+    pub client_certificate: Option<String>,
+    pub client_key: Option<String>,
+    // Base64 SHA-256 digests of trusted SubjectPublicKeyInfo, matched against the server certificate
+    #[serde(default)]
+    pub pins: Vec<String>,
+}
+
+fn configuration_error(path: &str, reason: impl std::fmt::Display) -> HttpError {
+    HttpError::ConfigurationError {
+        reason: format!("{}: {}", path, reason),
+    }
+}
+
+fn load_certificates(path: &str) -> Result<Vec<Certificate>, HttpError> {
+    let file = File::open(path).map_err(|err| configuration_error(path, err))?;
+    let certificates = rustls_pemfile::certs(&mut BufReader::new(file))
+        .map_err(|err| configuration_error(path, err))?;
+
+    match certificates.is_empty() {
+        true => Err(configuration_error(path, "no certificate found")),
+        false => Ok(certificates.into_iter().map(Certificate).collect()),
+    }
+}
+
+fn load_private_key(path: &str) -> Result<PrivateKey, HttpError> {
+    let file = File::open(path).map_err(|err| configuration_error(path, err))?;
+    let items = rustls_pemfile::read_all(&mut BufReader::new(file))
+        .map_err(|err| configuration_error(path, err))?;
+
+    items
+        .into_iter()
+        .find_map(|item| match item {
+            rustls_pemfile::Item::PKCS8Key(key)
+            | rustls_pemfile::Item::RSAKey(key)
+            | rustls_pemfile::Item::ECKey(key) => Some(PrivateKey(key)),
+            _ => None,
+        })
+        .ok_or_else(|| configuration_error(path, "no private key found"))
+}
+
+fn load_root_store(configuration: &TlsConfiguration) -> Result<RootCertStore, HttpError> {
+    let mut roots = RootCertStore::empty();
+
+    match rustls_native_certs::load_native_certs() {
+        Ok(certificates) => {
+            for certificate in certificates {
+                // Unparsable system certificates are skipped, as hyper-rustls does
+                let _ = roots.add(&Certificate(certificate.0));
+            }
+        }
+        Err(err) => debug!("Unable to load system CA certificates: {}", err),
+    }
+
+    for path in &configuration.ca_bundles {
+        for certificate in load_certificates(path)? {
+            roots
+                .add(&certificate)
+                .map_err(|err| configuration_error(path, err))?;
+        }
+    }
+
+    // Minimal images may lack a CA store
+    if roots.is_empty() {
+        warn!("No CA certificates found, falling back to embedded web PKI roots");
+        roots.add_server_trust_anchors(webpki_roots::TLS_SERVER_ROOTS.iter().map(|anchor| {
+            OwnedTrustAnchor::from_subject_spki_name_constraints(
+                anchor.subject,
+                anchor.spki,
+                anchor.name_constraints,
+            )
+        }));
+    }
+
+    Ok(roots)
+}
+
+fn spki_sha256(certificate: &Certificate) -> Option<String> {
+    let (_, certificate) = X509Certificate::from_der(&certificate.0).ok()?;
+    Some(base64::encode(Sha256::digest(certificate.public_key().raw)))
+}
+
+// Regular web PKI verification, then the server certificate must match a pin. Other
+// certificates sent by the server aren't necessarily part of the verified chain.
+struct PinnedCertVerifier {
+    verifier: WebPkiVerifier,
+    pins: Vec<String>,
+}
+
+impl ServerCertVerifier for PinnedCertVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &Certificate,
+        intermediates: &[Certificate],
+        server_name: &ServerName,
+        scts: &mut dyn Iterator<Item = &[u8]>,
+        ocsp_response: &[u8],
+        now: SystemTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        let verified = self.verifier.verify_server_cert(
+            end_entity,
+            intermediates,
+            server_name,
+            scts,
+            ocsp_response,
+            now,
+        )?;
+
+        let pinned = spki_sha256(end_entity).is_some_and(|digest| self.pins.contains(&digest));
+
+        match pinned {
+            true => Ok(verified),
+            false => Err(rustls::Error::General(
+                "server certificate doesn't match any pinned public key".to_string(),
+            )),
+        }
+    }
+}
+
+pub fn build_client_config(configuration: &TlsConfiguration) -> Result<ClientConfig, HttpError> {
+    let verifier = WebPkiVerifier::new(load_root_store(configuration)?, None);
+    let verifier: Arc<dyn ServerCertVerifier> = match configuration.pins.is_empty() {
+        true => Arc::new(verifier),
+        false => Arc::new(PinnedCertVerifier {
+            verifier,
+            pins: configuration.pins.clone(),
+        }),
+    };
+
+    let builder = ClientConfig::builder()
+        .with_safe_defaults()
+        .with_custom_certificate_verifier(verifier);
+
+    match (&configuration.client_certificate, &configuration.client_key) {
+        (Some(certificate), Some(key)) => builder
+            .with_single_cert(load_certificates(certificate)?, load_private_key(key)?)
+            .map_err(|err| configuration_error(key, err)),
+        (None, None) => Ok(builder.with_no_client_auth()),
+        _ => Err(HttpError::ConfigurationError {
+            reason: "client_certificate and client_key must be set together".to_string(),
+        }),
+    }
+}
@@ -1,26 +1,51 @@
+mod cluster;
 mod configuration;
 mod host;
 mod http_client;
+mod network;
 mod provider;
+mod secret;
+mod template;
+mod tls;
 
 use crate::configuration::CloudConfiguration;
 use crate::provider::error::CloudProviderError;
 use crate::provider::exoscale::ExoscaleCloudProvider;
-use crate::provider::{CloudInstance, CloudProvider, CloudInstanceGroup};
+use crate::provider::{CloudInstance, CloudInstanceGroup, CloudProvider};
 use env_logger::Env;
-use log::info;
+use log::{error, info, warn};
 
 async fn probe_exoscale(
-) -> Result<(CloudConfiguration, CloudInstance, Option<CloudInstanceGroup>), CloudProviderError> {
-    let mut provider = ExoscaleCloudProvider::new();
-    Ok(provider.probe().await?)
+    provider: &mut ExoscaleCloudProvider,
+) -> Result<
+    (
+        CloudConfiguration,
+        CloudInstance,
+        Option<CloudInstanceGroup>,
+    ),
+    CloudProviderError,
+> {
+    provider.probe().await
 }
 
-async fn cloud_init(configuration: CloudConfiguration, instance: CloudInstance) -> Result<(), host::HostError> {
+async fn cloud_init(
+    provider: &impl CloudProvider,
+    configuration: CloudConfiguration,
+    instance: CloudInstance,
+    group: Option<CloudInstanceGroup>,
+) -> Result<(), host::HostError> {
     if host::set_instance_hostname(instance.hostname.clone()).is_ok() {
         info!("Hostname set to {}", instance.hostname);
     }
 
+    if !instance.networks.is_empty() {
+        match host::configure_networks(&instance.networks) {
+            Ok(()) => info!("Network attachments configured"),
+            Err(error) => error!("Unable to configure network attachments: {}", error),
+        }
+    }
+
+    // Access is set up first, the following steps possibly waiting on other members
     host::ensure_directory("/var/lib/ssh".to_string())?;
 
     if host::ensure_ssh_hostkey("ed25519").is_ok() {
@@ -38,6 +63,80 @@ async fn cloud_init(configuration: CloudConfiguration, instance: CloudInstance)
         }
     }
 
+    if let Some(wireguard) = &configuration.network.wireguard {
+        if instance.membership_unknown {
+            warn!("Instance group membership unknown, skipping WireGuard");
+        } else if instance.ordinal.is_none() {
+            warn!("Instance group unknown, skipping WireGuard");
+        } else if let Err(error) =
+            network::bootstrap_wireguard(wireguard, provider, &instance, group.as_ref()).await
+        {
+            error!("Unable to configure WireGuard: {}", error);
+        }
+    }
+
+    if let Some(group) = &group {
+        match host::write_group_files(&instance, group) {
+            Ok(()) => info!("Instance group {} written", group.instance_group_id),
+            Err(error) => error!("Unable to write instance group: {}", error),
+        }
+
+        if configuration.host.manage_hosts {
+            match host::write_group_hosts(group) {
+                Ok(()) => info!("Hosts file updated with instance group"),
+                Err(error) => error!("Unable to update hosts file: {}", error),
+            }
+        }
+    }
+
+    if let Some(vault) = &configuration.tls.vault {
+        match tls::provision_vault_certificate(vault, &instance).await {
+            Ok(true) => info!("Certificate issued by Vault"),
+            Ok(false) => info!("Certificate from Vault still valid"),
+            Err(error) => error!("Unable to get a certificate from Vault: {}", error),
+        }
+    }
+
+    if let Some(acme) = &configuration.tls.acme {
+        match tls::provision_acme_certificate(acme, &instance).await {
+            Ok(true) => info!("Certificate issued by ACME CA"),
+            Ok(false) => info!("Certificate from ACME CA still valid"),
+            Err(error) => error!("Unable to get a certificate from ACME CA: {}", error),
+        }
+    }
+
+    if let Some(local) = &configuration.tls.local {
+        match tls::provision_local_certificate(local, &instance) {
+            Ok(true) => info!("Local certificate issued"),
+            Ok(false) => info!("Local certificate still valid"),
+            Err(error) => error!("Unable to issue a local certificate: {}", error),
+        }
+    }
+
+    if let Some(etcd) = &configuration.cluster.etcd {
+        if instance.membership_unknown {
+            warn!("Instance group membership unknown, skipping etcd");
+        } else if instance.ordinal.is_none() {
+            warn!("Instance group unknown, skipping etcd");
+        } else {
+            match cluster::bootstrap_etcd(etcd, &instance, group.as_ref()) {
+                Ok(()) => info!("etcd cluster configuration written"),
+                Err(error) => error!("Unable to configure etcd: {}", error),
+            }
+        }
+    }
+
+    for hook in configuration.host.hooks {
+        if hook.leader_only && !instance.is_leader {
+            continue;
+        }
+
+        info!("Running hook `{}`", hook.command);
+        if let Err(error) = host::run_hook(&hook.command, &instance) {
+            error!("Error: {}", error);
+        }
+    }
+
     Ok(())
 }
 
@@ -45,15 +144,35 @@ async fn cloud_init(configuration: CloudConfiguration, instance: CloudInstance)
 async fn main() {
     env_logger::Builder::from_env(Env::default().default_filter_or("info")).init();
 
-    let (configuration, instance, _group) =
-        if let Ok((configuration, instance, group)) = probe_exoscale().await {
+    let mut provider = ExoscaleCloudProvider::new();
+    let (configuration, mut instance, mut group) = match probe_exoscale(&mut provider).await {
+        Ok((configuration, instance, group)) => {
             info!("Loaded cloud init data from Exoscale platform");
             (configuration, instance, group)
-        } else {
+        }
+        Err(error) => {
+            error!(
+                "Unable to load cloud init data from Exoscale platform: {}",
+                error
+            );
+            return;
+        }
+    };
+
+    provider::elect_leader(&mut instance, group.as_mut());
+    if instance.is_leader {
+        info!("This instance is the leader");
+    }
+
+    let configuration = match configuration.render(&instance, group.as_ref()) {
+        Some(configuration) => configuration,
+        None => {
+            error!("Unable to render cloud configuration");
             return;
-        };
-    
-    if let Err(error) = cloud_init(configuration, instance).await {
-        info!("Error: {:?}", error);
+        }
+    };
+
+    if let Err(error) = cloud_init(&provider, configuration, instance, group).await {
+        error!("Error: {}", error);
     }
 }
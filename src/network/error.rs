@@ -0,0 +1,44 @@
+use crate::host::HostError;
+use crate::provider::error::CloudProviderError;
+use std::fmt;
+use std::io;
+
+#[allow(clippy::enum_variant_names)]
+#[derive(Debug)]
+pub enum NetworkError {
+    KeyError(String),
+    AddressError(String),
+    ProviderError(CloudProviderError),
+    HostError(HostError),
+    IOError(io::Error),
+}
+
+impl From<CloudProviderError> for NetworkError {
+    fn from(error: CloudProviderError) -> Self {
+        NetworkError::ProviderError(error)
+    }
+}
+
+impl From<HostError> for NetworkError {
+    fn from(error: HostError) -> Self {
+        NetworkError::HostError(error)
+    }
+}
+
+impl From<io::Error> for NetworkError {
+    fn from(error: io::Error) -> Self {
+        NetworkError::IOError(error)
+    }
+}
+
+impl fmt::Display for NetworkError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NetworkError::KeyError(reason) => write!(f, "invalid key: {}", reason),
+            NetworkError::AddressError(reason) => write!(f, "unable to assign address: {}", reason),
+            NetworkError::ProviderError(error) => write!(f, "{}", error),
+            NetworkError::HostError(error) => write!(f, "{}", error),
+            NetworkError::IOError(error) => write!(f, "I/O error: {}", error),
+        }
+    }
+}
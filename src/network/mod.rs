@@ -0,0 +1,5 @@
+mod error;
+mod wireguard;
+
+pub use error::NetworkError;
+pub use wireguard::{bootstrap_wireguard, WireguardConfiguration};
@@ -0,0 +1,410 @@
+use crate::host;
+use crate::network::NetworkError;
+use crate::provider::{AddressFamily, CloudInstance, CloudInstanceGroup, CloudProvider};
+use log::{debug, info, warn};
+use rand::rngs::OsRng;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fs;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+use std::time::{Duration, Instant};
+use x25519_dalek::{PublicKey, StaticSecret};
+
+const KEY_LENGTH: usize = 32;
+
+// systemd-networkd reads the private key file as the systemd-network user
+const PRIVATE_KEY_FILE_MODE: u32 = 0o640;
+
+const PEER_POLL_INTERVAL_SECS: u64 = 5;
+
+#[derive(Clone, Deserialize, Debug)]
+pub struct WireguardConfiguration {
+    #[serde(default = "default_interface")]
+    pub interface: String,
+    #[serde(default = "default_listen_port")]
+    pub listen_port: u16,
+    // Members get consecutive addresses in this range, in the order of their ordinal
+    #[serde(default = "default_address_range")]
+    pub address_range: String,
+    // Public address of the members used as endpoints
+    #[serde(default)]
+    pub address_family: AddressFamily,
+    // Kept across reboots so that the public key doesn't change
+    #[serde(default = "default_private_key_file")]
+    pub private_key_file: String,
+    #[serde(default = "default_private_key_owner")]
+    pub private_key_owner: String,
+    // Instance label holding the public key of each member
+    #[serde(default = "default_public_key_label")]
+    pub public_key_label: String,
+    // Shared directory used to exchange public keys instead of instance labels
+    pub key_store: Option<String>,
+    pub persistent_keepalive_secs: Option<u16>,
+    #[serde(default = "default_peer_wait_timeout_secs")]
+    pub peer_wait_timeout_secs: u64,
+}
+
+fn default_interface() -> String {
+    "wg0".to_string()
+}
+
+fn default_listen_port() -> u16 {
+    51820
+}
+
+fn default_address_range() -> String {
+    "10.254.0.0/24".to_string()
+}
+
+fn default_private_key_file() -> String {
+    "/var/lib/instance-init/wireguard/private.key".to_string()
+}
+
+fn default_private_key_owner() -> String {
+    "root:systemd-network".to_string()
+}
+
+fn default_public_key_label() -> String {
+    "wireguard-public-key".to_string()
+}
+
+fn default_peer_wait_timeout_secs() -> u64 {
+    300
+}
+
+fn decode_key(key: &str) -> Result<[u8; KEY_LENGTH], NetworkError> {
+    base64::decode(key.trim())
+        .map_err(|err| NetworkError::KeyError(err.to_string()))?
+        .try_into()
+        .map_err(|_| NetworkError::KeyError(format!("expected {} bytes", KEY_LENGTH)))
+}
+
+fn load_private_key(configuration: &WireguardConfiguration) -> Result<StaticSecret, NetworkError> {
+    let path = Path::new(&configuration.private_key_file);
+    if path.exists() {
+        let key = fs::read_to_string(path)?;
+        return Ok(StaticSecret::from(decode_key(&key)?));
+    }
+
+    info!("Generating WireGuard private key {}", path.display());
+    let secret = StaticSecret::random_from_rng(OsRng);
+    if let Some(directory) = path.parent() {
+        fs::create_dir_all(directory)?;
+    }
+
+    // Created with restricted permissions before the key is written
+    fs::write(path, "")?;
+    fs::set_permissions(path, fs::Permissions::from_mode(PRIVATE_KEY_FILE_MODE))?;
+    fs::write(path, format!("{}\n", base64::encode(secret.to_bytes())))?;
+    host::set_owner(path, &configuration.private_key_owner)?;
+
+    Ok(secret)
+}
+
+// The n-th host of the range, the network address itself being skipped
+fn overlay_address(range: &str, ordinal: usize) -> Result<(IpAddr, u8), NetworkError> {
+    let invalid = || NetworkError::AddressError(format!("invalid address range {}", range));
+    let (network, prefix_length) = range.split_once('/').ok_or_else(invalid)?;
+    let network = network.parse::<IpAddr>().map_err(|_| invalid())?;
+    let prefix_length = prefix_length.parse::<u8>().map_err(|_| invalid())?;
+    let offset = ordinal as u128 + 1;
+
+    let (address, host_bits) = match network {
+        IpAddr::V4(network) if prefix_length <= 32 => {
+            let host_bits = 32 - u32::from(prefix_length);
+            let mask = u32::MAX.checked_shl(host_bits).unwrap_or(0);
+            let address = u128::from(u32::from(network) & mask) + offset;
+            (IpAddr::V4(Ipv4Addr::from(address as u32)), host_bits)
+        }
+        IpAddr::V6(network) if prefix_length <= 128 => {
+            let host_bits = 128 - u32::from(prefix_length);
+            let mask = u128::MAX.checked_shl(host_bits).unwrap_or(0);
+            let address = (u128::from(network) & mask).wrapping_add(offset);
+            (IpAddr::V6(Ipv6Addr::from(address)), host_bits)
+        }
+        _ => return Err(invalid()),
+    };
+
+    // The last address of an IPv4 range is its broadcast address
+    let capacity = 1u128.checked_shl(host_bits).unwrap_or(u128::MAX) - 1;
+    if offset >= capacity {
+        return Err(NetworkError::AddressError(format!(
+            "ordinal {} doesn't fit in {}",
+            ordinal, range
+        )));
+    }
+
+    Ok((address, prefix_length))
+}
+
+fn host_prefix_length(address: &IpAddr) -> u8 {
+    match address {
+        IpAddr::V4(_) => 32,
+        IpAddr::V6(_) => 128,
+    }
+}
+
+async fn publish_public_key<P: CloudProvider>(
+    configuration: &WireguardConfiguration,
+    provider: &P,
+    instance: &CloudInstance,
+    public_key: &str,
+) -> Result<(), NetworkError> {
+    match &configuration.key_store {
+        Some(key_store) => {
+            fs::create_dir_all(key_store)?;
+            let path = Path::new(key_store).join(format!("{}.pub", instance.instance_id));
+            fs::write(path, format!("{}\n", public_key))?;
+        }
+        None => {
+            provider
+                .set_instance_label(instance, &configuration.public_key_label, public_key)
+                .await?
+        }
+    }
+
+    Ok(())
+}
+
+// Labels fetched along the group are used first, members are looked up again afterwards
+async fn peer_public_key<P: CloudProvider>(
+    configuration: &WireguardConfiguration,
+    provider: &P,
+    member: &CloudInstance,
+    refresh: bool,
+) -> Option<String> {
+    let key = match &configuration.key_store {
+        Some(key_store) => {
+            let path = Path::new(key_store).join(format!("{}.pub", member.instance_id));
+            fs::read_to_string(path).ok()
+        }
+        None if !refresh => member.labels.get(&configuration.public_key_label).cloned(),
+        None => match provider
+            .get_instance(&member.instance_id, &member.zone)
+            .await
+        {
+            Ok(member) => member.labels.get(&configuration.public_key_label).cloned(),
+            Err(error) => {
+                debug!(
+                    "Unable to look up instance {}: {}",
+                    member.instance_id, error
+                );
+                None
+            }
+        },
+    }?;
+
+    match decode_key(&key) {
+        Ok(_) => Some(key.trim().to_string()),
+        Err(error) => {
+            warn!(
+                "Ignoring public key of instance {}: {}",
+                member.instance_id, error
+            );
+            None
+        }
+    }
+}
+
+// Members publish their key while booting: peers are polled until every key is known
+// or the timeout is reached, missing peers being left out of the mesh
+async fn collect_peer_keys<P: CloudProvider>(
+    configuration: &WireguardConfiguration,
+    provider: &P,
+    peers: &[&CloudInstance],
+) -> HashMap<String, String> {
+    let deadline = Instant::now() + Duration::from_secs(configuration.peer_wait_timeout_secs);
+    let mut keys = HashMap::new();
+    let mut refresh = false;
+
+    loop {
+        for peer in peers {
+            if keys.contains_key(&peer.instance_id) {
+                continue;
+            }
+
+            if let Some(key) = peer_public_key(configuration, provider, peer, refresh).await {
+                keys.insert(peer.instance_id.clone(), key);
+            }
+        }
+
+        let missing = peers
+            .iter()
+            .filter(|peer| !keys.contains_key(&peer.instance_id))
+            .map(|peer| peer.instance_id.as_str())
+            .collect::<Vec<_>>();
+        if missing.is_empty() {
+            return keys;
+        }
+
+        if Instant::now() >= deadline {
+            warn!(
+                "No WireGuard public key for {}, left out of the mesh",
+                missing.join(", ")
+            );
+            return keys;
+        }
+
+        debug!(
+            "Waiting for WireGuard public keys of {}",
+            missing.join(", ")
+        );
+        tokio::time::sleep(Duration::from_secs(PEER_POLL_INTERVAL_SECS)).await;
+        refresh = true;
+    }
+}
+
+fn netdev_unit(
+    configuration: &WireguardConfiguration,
+    peers: &[(&CloudInstance, &String, IpAddr)],
+) -> String {
+    let mut unit = format!(
+        "[NetDev]\nName={}\nKind=wireguard\n\n[WireGuard]\nPrivateKeyFile={}\nListenPort={}\n",
+        configuration.interface, configuration.private_key_file, configuration.listen_port
+    );
+
+    for (peer, public_key, address) in peers {
+        unit.push_str(&format!(
+            "\n[WireGuardPeer]\nPublicKey={}\nAllowedIPs={}/{}\n",
+            public_key,
+            address,
+            host_prefix_length(address)
+        ));
+
+        match configuration.address_family.address(peer) {
+            Some(endpoint) if endpoint.contains(':') => unit.push_str(&format!(
+                "Endpoint=[{}]:{}\n",
+                endpoint, configuration.listen_port
+            )),
+            Some(endpoint) => unit.push_str(&format!(
+                "Endpoint={}:{}\n",
+                endpoint, configuration.listen_port
+            )),
+            None => {}
+        }
+
+        if let Some(keepalive) = configuration.persistent_keepalive_secs {
+            unit.push_str(&format!("PersistentKeepalive={}\n", keepalive));
+        }
+    }
+
+    unit
+}
+
+fn network_unit(
+    configuration: &WireguardConfiguration,
+    address: IpAddr,
+    prefix_length: u8,
+) -> String {
+    format!(
+        "[Match]\nName={}\n\n[Network]\nAddress={}/{}\n",
+        configuration.interface, address, prefix_length
+    )
+}
+
+// Sets up a full mesh between the members of the instance group, each one
+// reaching the others on their public address
+pub async fn bootstrap_wireguard<P: CloudProvider>(
+    configuration: &WireguardConfiguration,
+    provider: &P,
+    instance: &CloudInstance,
+    group: Option<&CloudInstanceGroup>,
+) -> Result<(), NetworkError> {
+    let ordinal = instance.ordinal.ok_or_else(|| {
+        NetworkError::AddressError("instance is not a member of its group".to_string())
+    })?;
+    let (address, prefix_length) = overlay_address(&configuration.address_range, ordinal)?;
+
+    let private_key = load_private_key(configuration)?;
+    let public_key = base64::encode(PublicKey::from(&private_key).as_bytes());
+    publish_public_key(configuration, provider, instance, &public_key).await?;
+    info!("WireGuard public key {} published", public_key);
+
+    let members = group
+        .map(|group| group.instances.iter().collect::<Vec<_>>())
+        .unwrap_or_default();
+    let peers = members
+        .into_iter()
+        .filter(|member| member.instance_id != instance.instance_id)
+        .collect::<Vec<_>>();
+    let keys = collect_peer_keys(configuration, provider, &peers).await;
+
+    let mut mesh = Vec::new();
+    for peer in peers {
+        let (Some(public_key), Some(ordinal)) = (keys.get(&peer.instance_id), peer.ordinal) else {
+            continue;
+        };
+        let (peer_address, _) = overlay_address(&configuration.address_range, ordinal)?;
+        mesh.push((peer, public_key, peer_address));
+    }
+
+    let interface = &configuration.interface;
+    host::write_network_units(&[
+        (
+            format!("{}.netdev", interface),
+            netdev_unit(configuration, &mesh),
+        ),
+        (
+            format!("{}.network", interface),
+            network_unit(configuration, address, prefix_length),
+        ),
+    ])?;
+    info!(
+        "WireGuard interface {} configured with {} peers",
+        interface,
+        mesh.len()
+    );
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn address(range: &str, ordinal: usize) -> Option<(String, u8)> {
+        overlay_address(range, ordinal)
+            .ok()
+            .map(|(address, prefix_length)| (address.to_string(), prefix_length))
+    }
+
+    #[test]
+    fn maps_ordinals_to_consecutive_addresses() {
+        assert_eq!(address("10.254.0.0/24", 0), Some(("10.254.0.1".into(), 24)));
+        assert_eq!(address("10.254.0.0/24", 5), Some(("10.254.0.6".into(), 24)));
+        // Host bits of the range are ignored
+        assert_eq!(address("10.254.0.7/24", 0), Some(("10.254.0.1".into(), 24)));
+        assert_eq!(
+            address("10.254.0.0/16", 255),
+            Some(("10.254.1.0".into(), 16))
+        );
+        assert_eq!(address("fd00:77::/64", 0), Some(("fd00:77::1".into(), 64)));
+        assert_eq!(address("fd00:77::/64", 9), Some(("fd00:77::a".into(), 64)));
+    }
+
+    #[test]
+    fn rejects_ordinals_outside_of_range() {
+        // Network and broadcast addresses are never given out
+        assert_eq!(address("10.254.0.0/30", 1), Some(("10.254.0.2".into(), 30)));
+        assert_eq!(address("10.254.0.0/30", 2), None);
+        assert_eq!(
+            address("10.254.0.0/24", 253),
+            Some(("10.254.0.254".into(), 24))
+        );
+        assert_eq!(address("10.254.0.0/24", 254), None);
+        assert_eq!(address("10.254.0.1/32", 0), None);
+        assert_eq!(address("fd00:77::/127", 1), None);
+    }
+
+    #[test]
+    fn rejects_invalid_ranges() {
+        for range in ["10.254.0.0", "10.254.0.0/33", "fd00::/129", "overlay/24"] {
+            assert!(matches!(
+                overlay_address(range, 0),
+                Err(NetworkError::AddressError(_))
+            ));
+        }
+    }
+}
@@ -1,15 +1,47 @@
 use crate::http_client::HttpError;
+use std::error::Error;
+use std::fmt;
 
-#[derive(Clone, Debug, PartialEq)]
+#[derive(Clone, Debug)]
 pub enum CloudProviderError {
     AuthenticationError,
     NotAvailable,
-    ResourceUnreachable,
+    ResourceUnreachable(HttpError),
     ConfigurationError,
+    OperationFailed(String),
+    GroupNotReady(String),
+}
+
+impl fmt::Display for CloudProviderError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CloudProviderError::AuthenticationError => write!(f, "authentication failed"),
+            CloudProviderError::NotAvailable => write!(f, "cloud provider not available"),
+            CloudProviderError::ResourceUnreachable(error) => {
+                write!(f, "resource unreachable: {}", error)
+            }
+            CloudProviderError::ConfigurationError => write!(f, "invalid configuration"),
+            CloudProviderError::OperationFailed(reason) => {
+                write!(f, "operation failed: {}", reason)
+            }
+            CloudProviderError::GroupNotReady(reason) => {
+                write!(f, "instance group not ready: {}", reason)
+            }
+        }
+    }
+}
+
+impl Error for CloudProviderError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            CloudProviderError::ResourceUnreachable(error) => Some(error),
+            _ => None,
+        }
+    }
 }
 
 impl From<HttpError> for CloudProviderError {
-    fn from(_: HttpError) -> Self {
-        CloudProviderError::ResourceUnreachable
+    fn from(error: HttpError) -> Self {
+        CloudProviderError::ResourceUnreachable(error)
     }
 }
@@ -4,7 +4,7 @@ use hmac::{Hmac, Mac, NewMac};
 use log::error;
 use serde::Deserialize;
 use sha2::Sha256;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 // A small subset of the API responses
@@ -26,12 +26,86 @@ pub struct ExoscaleInstance {
     pub ipv4_address: Option<String>,
     #[serde(rename = "ipv6-address")]
     pub ipv6_address: Option<String>,
+    #[serde(rename = "private-networks", default)]
+    pub private_networks: Vec<ExoscaleInstancePrivateNetwork>,
+    #[serde(rename = "elastic-ips", default)]
+    pub elastic_ips: Vec<ExoscaleReference>,
+    #[serde(default)]
+    pub labels: HashMap<String, String>,
+    #[serde(rename = "security-groups", default)]
+    pub security_groups: Vec<ExoscaleReference>,
+    #[serde(rename = "anti-affinity-groups", default)]
+    pub anti_affinity_groups: Vec<ExoscaleReference>,
+    #[serde(rename = "instance-type")]
+    pub instance_type: Option<ExoscaleReference>,
+    pub template: Option<ExoscaleReference>,
+    pub state: Option<String>,
+    #[serde(rename = "public-ip-assignment")]
+    pub public_ip_assignment: Option<String>,
+}
+
+#[derive(Clone, Deserialize, Debug)]
+pub struct ExoscaleReference {
+    pub id: String,
+}
+
+#[derive(Clone, Deserialize, Debug)]
+pub struct ExoscaleNamedResource {
+    pub name: String,
+}
+
+#[derive(Clone, Deserialize, Debug)]
+pub struct ExoscaleInstanceType {
+    pub family: String,
+    pub size: String,
+}
+
+#[derive(Clone, Deserialize, Debug)]
+pub struct ExoscaleInstancePrivateNetwork {
+    pub id: String,
+    #[serde(rename = "mac-address")]
+    pub mac_address: String,
+}
+
+#[derive(Clone, Deserialize, Debug)]
+pub struct ExoscalePrivateNetworkLease {
+    #[serde(rename = "instance-id")]
+    pub instance_id: String,
+    #[serde(rename = "ip-address")]
+    pub ip_address: String,
+}
+
+// Managed private networks have an address range served by DHCP
+#[derive(Clone, Deserialize, Debug)]
+pub struct ExoscalePrivateNetwork {
+    pub id: String,
+    pub name: Option<String>,
+    #[serde(rename = "start-ip")]
+    pub start_ip: Option<String>,
+    pub netmask: Option<String>,
+    #[serde(default)]
+    pub leases: Vec<ExoscalePrivateNetworkLease>,
+}
+
+#[derive(Clone, Deserialize, Debug)]
+pub struct ExoscaleElasticIp {
+    pub id: String,
+    pub ip: String,
 }
 
 #[derive(Clone, Deserialize, Debug)]
 pub struct ExoscaleInstancePool {
     pub size: usize,
-    pub instances: Vec<ExoscaleInstance>,
+    #[serde(default)]
+    pub instances: Vec<ExoscaleReference>,
+}
+
+#[derive(Clone, Deserialize, Debug)]
+pub struct ExoscaleOperation {
+    pub id: String,
+    pub state: String,
+    pub reason: Option<String>,
+    pub message: Option<String>,
 }
 
 fn build_expiration_timestamp(timeout: u64) -> Result<u64, CloudProviderError> {
@@ -63,37 +137,32 @@ fn build_authentication_header(
     }
 }
 
-pub async fn build_signature(
-    credentials: ExoscaleAPICredentials,
+// Signs a request following the EXO2-HMAC-SHA256 scheme: the message is made of the
+// method and path, the body, the values of the query parameters concatenated in
+// the alphabetical order of their names, the (unused) signed headers and the
+// expiration timestamp, one per line.
+pub fn sign_request(
+    credentials: &ExoscaleAPICredentials,
     method: &str,
-    path: String,
+    path: &str,
     body: Option<&str>,
-    params: Option<HashMap<&str, &str>>,
+    params: &BTreeMap<&str, &str>,
+    expiration: u64,
 ) -> Result<String, CloudProviderError> {
-    let expiration = build_expiration_timestamp(120)?;
-
-    let body = body.unwrap_or_default();
-    let params = params.unwrap_or_default();
-
-    let (param_args, param_values) = params.into_iter().fold(
-        (Vec::new(), Vec::new()),
-        |(mut args, mut values), (key, value)| {
-            args.push(key);
-            values.push(value);
-            (args, values)
-        },
-    );
-
-    let param_args = param_args.join(":");
-    let param_values = param_values.join(":");
+    let param_args = params.keys().copied().collect::<Vec<_>>().join(";");
+    let param_values = params.values().copied().collect::<String>();
 
     let message = format!(
         "{} {}\n{}\n{}\n\n{}",
-        method, path, body, param_values, expiration
+        method,
+        path,
+        body.unwrap_or_default(),
+        param_values,
+        expiration
     );
 
-    let mut mac =
-        Hmac::<Sha256>::new_from_slice(credentials.api_secret.as_bytes()).map_err(|err| {
+    let mut mac = Hmac::<Sha256>::new_from_slice(credentials.api_secret.expose().as_bytes())
+        .map_err(|err| {
             error!("Error while building signature: {:?}", err);
             CloudProviderError::AuthenticationError
         })?;
@@ -102,12 +171,113 @@ pub async fn build_signature(
     let signature = mac.finalize().into_bytes();
     let signature = base64::encode(signature);
 
-    let auth_header = build_authentication_header(
+    Ok(build_authentication_header(
         credentials.api_key.as_str(),
         param_args.as_str(),
         expiration,
         signature,
-    );
+    ))
+}
+
+pub fn build_signature(
+    credentials: &ExoscaleAPICredentials,
+    method: &str,
+    path: &str,
+    body: Option<&str>,
+    params: &BTreeMap<&str, &str>,
+) -> Result<String, CloudProviderError> {
+    let expiration = build_expiration_timestamp(120)?;
+    sign_request(credentials, method, path, body, params, expiration)
+}
 
-    Ok(auth_header)
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::secret::Secret;
+
+    const API_KEY: &str = "EXO0123456789abcdef01234567";
+    const API_SECRET: &str = "abcdefghijklmnopqrstuvwxyz0123456789ABCDEFG";
+    const EXPIRATION: u64 = 1700000000;
+
+    fn credentials() -> ExoscaleAPICredentials {
+        ExoscaleAPICredentials {
+            api_key: API_KEY.to_string(),
+            api_secret: Secret::new(API_SECRET.to_string()),
+        }
+    }
+
+    #[test]
+    fn signs_query_args_in_name_order() {
+        let mut params = BTreeMap::new();
+        params.insert("zone", "ch-gva-2");
+        params.insert("ip-address", "10.0.0.1");
+
+        let header = sign_request(
+            &credentials(),
+            "GET",
+            "/v2/instance",
+            None,
+            &params,
+            EXPIRATION,
+        )
+        .unwrap();
+        assert_eq!(
+            header,
+            "EXO2-HMAC-SHA256 credential=EXO0123456789abcdef01234567,signed-query-args=ip-address;zone,\
+             expires=1700000000,signature=7w+pytw4lBnZOjThS2KM9XBX189ky4paRnxVONJ6Yek="
+        );
+    }
+
+    #[test]
+    fn signs_json_body() {
+        let body = r#"{"labels":{"role":"db"}}"#;
+        let header = sign_request(
+            &credentials(),
+            "PUT",
+            "/v2/instance/4f2e",
+            Some(body),
+            &BTreeMap::new(),
+            EXPIRATION,
+        )
+        .unwrap();
+        assert_eq!(
+            header,
+            "EXO2-HMAC-SHA256 credential=EXO0123456789abcdef01234567,\
+             expires=1700000000,signature=5wphW9soNgaAuWk8TAusiuWlSxck66n5h3+oQBBRLF4="
+        );
+    }
+
+    #[test]
+    fn signs_empty_body() {
+        let header = sign_request(
+            &credentials(),
+            "GET",
+            "/v2/zone",
+            None,
+            &BTreeMap::new(),
+            EXPIRATION,
+        )
+        .unwrap();
+        assert_eq!(
+            header,
+            "EXO2-HMAC-SHA256 credential=EXO0123456789abcdef01234567,\
+             expires=1700000000,signature=Nwt9ozw3ggLy2Co1WdkUZ+wFLxPoAOaxwTdWDQEGtD0="
+        );
+    }
+
+    #[test]
+    fn build_signature_expires_later() {
+        let header =
+            build_signature(&credentials(), "GET", "/v2/zone", None, &BTreeMap::new()).unwrap();
+        let expires = header
+            .split(',')
+            .find_map(|field| field.strip_prefix("expires="))
+            .and_then(|expires| expires.parse::<u64>().ok())
+            .unwrap();
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        assert!(expires > now && expires <= now + 120);
+    }
 }
@@ -1,13 +1,65 @@
+use crate::http_client::{ProxyConfiguration, TlsConfiguration};
+use crate::secret::Secret;
 use serde::Deserialize;
 
-#[derive(Clone, Deserialize, Debug, Default)]
+// What to do when the instance pool isn't ready in time
+#[derive(Clone, Copy, Deserialize, Debug, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum PoolWaitPolicy {
+    // Go on as a standalone instance
+    #[default]
+    Continue,
+    // Stop without configuring the instance
+    Fail,
+    // Go on with the instances ready so far
+    Partial,
+}
+
+// Credentials are optional here: they are preferably provided by the image or
+// the environment rather than through user-data
+#[derive(Clone, Deserialize, Debug)]
 pub struct ExoscaleCloudProviderConfiguration {
-    pub api_key: String,
-    pub api_secret: String,
+    pub api_key: Option<String>,
+    pub api_secret: Option<Secret>,
     #[serde(default = "default_api_timeout_secs")]
     pub api_timeout_secs: u64,
     #[serde(default = "default_api_retry_delay_secs")]
     pub api_retry_delay_secs: u64,
+    #[serde(default = "default_api_retry_max_attempts")]
+    pub api_retry_max_attempts: u32,
+    #[serde(default = "default_api_retry_deadline_secs")]
+    pub api_retry_deadline_secs: u64,
+    #[serde(default = "default_api_concurrency")]
+    pub api_concurrency: usize,
+    #[serde(default = "default_pool_wait_timeout_secs")]
+    pub pool_wait_timeout_secs: u64,
+    #[serde(default)]
+    pub pool_wait_policy: PoolWaitPolicy,
+    #[serde(default = "default_api_max_body_size")]
+    pub api_max_body_size: usize,
+    #[serde(default)]
+    pub api_tls: TlsConfiguration,
+    #[serde(default)]
+    pub api_proxy: ProxyConfiguration,
+}
+
+impl Default for ExoscaleCloudProviderConfiguration {
+    fn default() -> Self {
+        Self {
+            api_key: None,
+            api_secret: None,
+            api_timeout_secs: default_api_timeout_secs(),
+            api_retry_delay_secs: default_api_retry_delay_secs(),
+            api_retry_max_attempts: default_api_retry_max_attempts(),
+            api_retry_deadline_secs: default_api_retry_deadline_secs(),
+            api_concurrency: default_api_concurrency(),
+            pool_wait_timeout_secs: default_pool_wait_timeout_secs(),
+            pool_wait_policy: PoolWaitPolicy::default(),
+            api_max_body_size: default_api_max_body_size(),
+            api_tls: TlsConfiguration::default(),
+            api_proxy: ProxyConfiguration::default(),
+        }
+    }
 }
 
 fn default_api_timeout_secs() -> u64 {
@@ -17,3 +69,23 @@ fn default_api_timeout_secs() -> u64 {
 fn default_api_retry_delay_secs() -> u64 {
     5
 }
+
+fn default_api_retry_max_attempts() -> u32 {
+    5
+}
+
+fn default_api_retry_deadline_secs() -> u64 {
+    60
+}
+
+fn default_api_concurrency() -> usize {
+    8
+}
+
+fn default_pool_wait_timeout_secs() -> u64 {
+    600
+}
+
+fn default_api_max_body_size() -> usize {
+    16 * 1024 * 1024
+}
@@ -0,0 +1,149 @@
+use crate::provider::exoscale::{ExoscaleAPICredentials, ExoscaleCloudProviderConfiguration};
+use crate::secret::Secret;
+use log::{error, info, warn};
+use serde::Deserialize;
+use std::env;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// Credentials are looked up from the most to the least specific source. Only
+// the first one providing both the key and the secret is used.
+const API_KEY_ENV: &str = "EXOSCALE_API_KEY";
+const API_SECRET_ENV: &str = "EXOSCALE_API_SECRET";
+
+// systemd credentials (LoadCredential=, SetCredentialEncrypted=...) of the unit
+const CREDENTIALS_DIRECTORY_ENV: &str = "CREDENTIALS_DIRECTORY";
+const API_KEY_CREDENTIAL: &str = "exoscale-api-key";
+const API_SECRET_CREDENTIAL: &str = "exoscale-api-secret";
+
+// Instance-local file first, then the one baked into the image
+const CREDENTIALS_PATHS: [&str; 2] = [
+    "/etc/instance-init/exoscale-credentials.toml",
+    "/usr/lib/instance-init/exoscale-credentials.toml",
+];
+
+#[derive(Deserialize)]
+struct CredentialsFile {
+    api_key: String,
+    api_secret: Secret,
+}
+
+enum CredentialsSource {
+    Environment,
+    SystemdCredential(PathBuf),
+    File(PathBuf),
+    UserData,
+}
+
+impl fmt::Display for CredentialsSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CredentialsSource::Environment => {
+                write!(f, "environment ({}, {})", API_KEY_ENV, API_SECRET_ENV)
+            }
+            CredentialsSource::SystemdCredential(path) => {
+                write!(f, "systemd credentials ({})", path.display())
+            }
+            CredentialsSource::File(path) => write!(f, "file {}", path.display()),
+            CredentialsSource::UserData => write!(f, "user-data"),
+        }
+    }
+}
+
+fn from_environment() -> Option<ExoscaleAPICredentials> {
+    match (env::var(API_KEY_ENV).ok(), env::var(API_SECRET_ENV).ok()) {
+        (Some(api_key), Some(api_secret)) => Some(ExoscaleAPICredentials {
+            api_key,
+            api_secret: Secret::new(api_secret),
+        }),
+        (None, None) => None,
+        _ => {
+            warn!("Ignoring incomplete Exoscale API credentials from environment");
+            None
+        }
+    }
+}
+
+fn read_credential(directory: &Path, name: &str) -> Option<String> {
+    let path = directory.join(name);
+    match fs::read_to_string(&path) {
+        Ok(value) => Some(value.trim().to_string()),
+        Err(err) => {
+            if path.exists() {
+                error!("Unable to read credential {}: {}", path.display(), err);
+            }
+            None
+        }
+    }
+}
+
+fn from_systemd_credentials(directory: &Path) -> Option<ExoscaleAPICredentials> {
+    let api_key = read_credential(directory, API_KEY_CREDENTIAL)?;
+    let api_secret = read_credential(directory, API_SECRET_CREDENTIAL)?;
+
+    Some(ExoscaleAPICredentials {
+        api_key,
+        api_secret: Secret::new(api_secret),
+    })
+}
+
+fn from_file(path: &Path) -> Option<ExoscaleAPICredentials> {
+    let content = fs::read_to_string(path)
+        .map_err(|err| error!("Unable to read {}: {}", path.display(), err))
+        .ok()?;
+    let file: CredentialsFile = toml::from_str(&content)
+        .map_err(|err| error!("Invalid credentials file {}: {}", path.display(), err))
+        .ok()?;
+
+    Some(ExoscaleAPICredentials {
+        api_key: file.api_key,
+        api_secret: file.api_secret,
+    })
+}
+
+fn from_user_data(
+    configuration: &ExoscaleCloudProviderConfiguration,
+) -> Option<ExoscaleAPICredentials> {
+    Some(ExoscaleAPICredentials {
+        api_key: configuration.api_key.clone()?,
+        api_secret: configuration.api_secret.clone()?,
+    })
+}
+
+pub fn load_credentials(
+    configuration: &ExoscaleCloudProviderConfiguration,
+) -> Option<ExoscaleAPICredentials> {
+    let mut found =
+        from_environment().map(|credentials| (credentials, CredentialsSource::Environment));
+
+    if found.is_none() {
+        found = env::var(CREDENTIALS_DIRECTORY_ENV)
+            .ok()
+            .and_then(|directory| {
+                let directory = PathBuf::from(directory);
+                from_systemd_credentials(&directory).map(|credentials| {
+                    (credentials, CredentialsSource::SystemdCredential(directory))
+                })
+            });
+    }
+
+    if found.is_none() {
+        found = CREDENTIALS_PATHS
+            .iter()
+            .map(PathBuf::from)
+            .filter(|path| path.exists())
+            .find_map(|path| {
+                from_file(&path).map(|credentials| (credentials, CredentialsSource::File(path)))
+            });
+    }
+
+    if found.is_none() {
+        found = from_user_data(configuration)
+            .map(|credentials| (credentials, CredentialsSource::UserData));
+    }
+
+    let (credentials, source) = found?;
+    info!("Using Exoscale API credentials from {}", source);
+    Some(credentials)
+}
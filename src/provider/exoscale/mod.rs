@@ -1,100 +1,147 @@
 mod api;
+mod client;
 mod configuration;
+mod credentials;
 
 use crate::configuration::CloudConfiguration;
-use crate::http_client::HttpClient;
+use crate::http_client::{HttpClient, HttpRequest, RetryPolicy};
 use crate::provider::error::CloudProviderError;
-use crate::provider::{CloudInstance, CloudInstanceGroup, CloudProvider};
+use crate::provider::exoscale::api::{
+    ExoscaleElasticIp, ExoscaleInstanceType, ExoscaleNamedResource, ExoscaleOperation,
+    ExoscalePrivateNetwork, ExoscaleReference,
+};
+use crate::provider::exoscale::client::ExoscaleAPIClient;
+use crate::provider::exoscale::credentials::load_credentials;
+use crate::provider::{
+    metadata_endpoints, CloudInstance, CloudInstanceGroup, CloudNetworkAttachment, CloudProvider,
+};
+use crate::secret::Secret;
 use async_trait::async_trait;
-use hyper::header::AUTHORIZATION;
-use log::{info, debug, error};
+use futures::stream::{self, StreamExt};
+use hyper::Method;
+use log::{debug, error, info, warn};
+use serde_json::json;
 use std::collections::HashMap;
+use std::net::Ipv4Addr;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{Duration, Instant};
+use tokio::sync::OnceCell;
 
-pub use api::{build_signature, ExoscaleInstance, ExoscaleInstanceManager, ExoscaleInstancePool};
-pub use configuration::ExoscaleCloudProviderConfiguration;
+pub use api::{ExoscaleInstance, ExoscaleInstancePool};
+pub use configuration::{ExoscaleCloudProviderConfiguration, PoolWaitPolicy};
 
 const EXOSCALE_CLOUD_IDENTIFIER: &str = "Exoscale Compute Platform";
+const EXOSCALE_INSTANCE_POOL_MANAGER: &str = "instance-pool";
+const EXOSCALE_INSTANCE_RUNNING: &str = "running";
+const EXOSCALE_POOL_PROGRESS_INTERVAL_SECS: u64 = 30;
+const EXOSCALE_OPERATION_TIMEOUT_SECS: u64 = 60;
 
+const EXOSCALE_METADATA_ENDPOINTS: [&str; 1] = ["http://169.254.169.254"];
 const EXOSCALE_METADATA_DEFAULT_TIMEOUT_SECS: u64 = 5;
 const EXOSCALE_API_DEFAULT_TIMEOUT_SECS: u64 = 5;
+const EXOSCALE_API_DEFAULT_CONCURRENCY: usize = 8;
+
+// The metadata server may not be reachable right after the network is up
+const EXOSCALE_METADATA_RETRY_ATTEMPTS: u32 = 10;
+const EXOSCALE_METADATA_RETRY_DEADLINE_SECS: u64 = 60;
+const EXOSCALE_RETRY_INITIAL_DELAY_MILLIS: u64 = 500;
+const EXOSCALE_RETRY_MAX_DELAY_SECS: u64 = 5;
 
 pub struct ExoscaleCloudProvider {
-    credentials: Option<ExoscaleAPICredentials>,
+    metadata_endpoints: Vec<String>,
     metadata_client: HttpClient,
-    api_client: HttpClient,
+    api_client: ExoscaleAPIClient,
+    resource_names: Mutex<HashMap<String, Arc<OnceCell<String>>>>,
+    ready_instances: Mutex<HashMap<String, CloudInstance>>,
+    api_concurrency: usize,
 }
 
 #[derive(Clone, Debug)]
 pub struct ExoscaleAPICredentials {
     pub api_key: String,
-    pub api_secret: String,
+    pub api_secret: Secret,
 }
 
 impl ExoscaleCloudProvider {
     pub fn new() -> ExoscaleCloudProvider {
-        let metadata_client = HttpClient::new(EXOSCALE_METADATA_DEFAULT_TIMEOUT_SECS);
-        let api_client = HttpClient::new(EXOSCALE_API_DEFAULT_TIMEOUT_SECS);
+        let mut metadata_client = HttpClient::new(EXOSCALE_METADATA_DEFAULT_TIMEOUT_SECS);
+        metadata_client.set_retry_policy(Self::retry_policy(
+            EXOSCALE_METADATA_RETRY_ATTEMPTS,
+            EXOSCALE_METADATA_RETRY_DEADLINE_SECS,
+        ));
+        let metadata_endpoints = metadata_endpoints(&EXOSCALE_METADATA_ENDPOINTS);
+        metadata_client.set_metadata_endpoints(&metadata_endpoints);
+        let api_client = ExoscaleAPIClient::new(EXOSCALE_API_DEFAULT_TIMEOUT_SECS);
 
         ExoscaleCloudProvider {
-            credentials: None,
+            metadata_endpoints,
             metadata_client,
             api_client,
+            resource_names: Mutex::new(HashMap::new()),
+            ready_instances: Mutex::new(HashMap::new()),
+            api_concurrency: EXOSCALE_API_DEFAULT_CONCURRENCY,
         }
     }
 
+    fn retry_policy(max_attempts: u32, deadline_secs: u64) -> RetryPolicy {
+        RetryPolicy::new(
+            max_attempts,
+            Duration::from_millis(EXOSCALE_RETRY_INITIAL_DELAY_MILLIS),
+            Duration::from_secs(EXOSCALE_RETRY_MAX_DELAY_SECS),
+        )
+        .with_deadline(Duration::from_secs(deadline_secs))
+    }
+
     pub fn set_api_timeout(&mut self, timeout_secs: u64) {
         self.api_client.set_timeout(timeout_secs);
     }
 
-    pub fn set_api_credentials(&mut self, credentials: ExoscaleAPICredentials) {
-        self.credentials = Some(credentials);
+    pub fn set_api_retry_policy(&mut self, max_attempts: u32, deadline_secs: u64) {
+        self.api_client
+            .set_retry_policy(Self::retry_policy(max_attempts, deadline_secs));
     }
 
-    async fn metadata_get(&self, path: &str) -> Result<String, CloudProviderError> {
-        debug!("Retrieving metadata from path: {}", path);
-        let uri = format!("http://169.254.169.254/latest/{}", path);
-        Ok(self.metadata_client.request_get(uri).await?)
+    pub fn set_api_concurrency(&mut self, concurrency: usize) {
+        self.api_concurrency = concurrency.max(1);
     }
 
-    async fn api_get<T>(&self, zone: &str, path: &str) -> Result<T, CloudProviderError>
-    where
-        T: serde::de::DeserializeOwned,
-    {
-        debug!("Retrieving API data from path: {}", path);
-        let path = format!("/v2/{}", path);
-        let uri = format!("https://api-{}.exoscale.com{}", zone, path);
-
-        let credentials = self
-            .credentials
-            .clone()
-            .ok_or(CloudProviderError::AuthenticationError)?;
-
-        let signature = build_signature(credentials, "GET", path, None, None).await?;
-
-        let mut headers = HashMap::new();
-        headers.insert(AUTHORIZATION.to_string(), signature);
+    pub fn set_api_credentials(&mut self, credentials: ExoscaleAPICredentials) {
+        self.api_client.set_credentials(credentials);
+    }
 
-        let response = self
-            .api_client
-            .request_get_with_headers(uri, headers)
-            .await?;
+    async fn metadata_get(&self, path: &str) -> Result<String, CloudProviderError> {
+        self.metadata_send(path, false).await
+    }
 
-        serde_json::from_str(&response).map_err(|err| {
-            error!("Exoscale API deserialization: {}", err);
-            CloudProviderError::NotAvailable
-        })
+    // User-data isn't logged: its secrets are only known once it is parsed
+    async fn metadata_send(
+        &self,
+        path: &str,
+        sensitive: bool,
+    ) -> Result<String, CloudProviderError> {
+        debug!("Retrieving metadata from path: {}", path);
+        let requests = self
+            .metadata_endpoints
+            .iter()
+            .map(|endpoint| HttpRequest::get(format!("{}/latest/{}", endpoint, path)))
+            .map(|request| match sensitive {
+                true => request.sensitive(),
+                false => request,
+            })
+            .collect();
+        Ok(self.metadata_client.send_any(requests).await?.body)
     }
 
     pub async fn probe_basic_instance_data(&self) -> Result<CloudInstance, CloudProviderError> {
         let instance_id = self.get_metadata_instance_id().await?;
         debug!("Found instance id = {}", instance_id);
-    
+
         let hostname = self.get_metadata_hostname().await?;
         debug!("Found hostname = {}", hostname);
-    
+
         let zone = self.get_metadata_zone().await?;
         debug!("Found zone = {}", zone);
-    
+
         Ok(CloudInstance {
             instance_id,
             hostname,
@@ -103,55 +150,410 @@ impl ExoscaleCloudProvider {
         })
     }
 
-    pub async fn probe_advanced_instance_data(&mut self, configuration: &CloudConfiguration, instance: &CloudInstance) -> Result<(CloudInstance, Option<CloudInstanceGroup>), CloudProviderError> {
+    async fn fetch_resource_name(
+        &self,
+        zone: &str,
+        path: &str,
+    ) -> Result<String, CloudProviderError> {
+        match path.starts_with("/instance-type/") {
+            true => {
+                let instance_type: ExoscaleInstanceType = self.api_client.get(zone, path).await?;
+                Ok(format!("{}.{}", instance_type.family, instance_type.size))
+            }
+            false => {
+                let resource: ExoscaleNamedResource = self.api_client.get(zone, path).await?;
+                Ok(resource.name)
+            }
+        }
+    }
+
+    // Names of the resources referenced by instances, which are usually shared
+    // by every member of a pool. Concurrent lookups of the same resource wait
+    // for the first one.
+    async fn resource_name(&self, zone: &str, path: &str) -> Result<String, CloudProviderError> {
+        let name = self
+            .resource_names
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .entry(path.to_string())
+            .or_default()
+            .clone();
+
+        let name = name
+            .get_or_try_init(|| self.fetch_resource_name(zone, path))
+            .await?;
+        Ok(name.clone())
+    }
+
+    // Best-effort: resources the API key isn't allowed to read are left out
+    async fn resource_names(
+        &self,
+        zone: &str,
+        kind: &str,
+        references: &[ExoscaleReference],
+    ) -> Vec<String> {
+        let mut names = Vec::with_capacity(references.len());
+        for reference in references {
+            match self
+                .resource_name(zone, &format!("/{}/{}", kind, reference.id))
+                .await
+            {
+                Ok(name) => names.push(name),
+                Err(error) => warn!("Unable to get {} {}: {}", kind, reference.id, error),
+            }
+        }
+        names
+    }
+
+    async fn to_cloud_instance(
+        &self,
+        instance: ExoscaleInstance,
+        zone: &str,
+    ) -> Result<CloudInstance, CloudProviderError> {
+        let security_groups = self
+            .resource_names(zone, "security-group", &instance.security_groups)
+            .await;
+        let anti_affinity_groups = self
+            .resource_names(zone, "anti-affinity-group", &instance.anti_affinity_groups)
+            .await;
+        let instance_type = match &instance.instance_type {
+            Some(reference) => self
+                .resource_name(zone, &format!("/instance-type/{}", reference.id))
+                .await
+                .map_err(|error| warn!("Unable to get instance type {}: {}", reference.id, error))
+                .ok(),
+            None => None,
+        };
+
+        let ready = Self::is_ready(&instance);
+
+        Ok(CloudInstance {
+            instance_id: instance.id,
+            manager_id: instance
+                .manager
+                .filter(|manager| manager.manager_type == EXOSCALE_INSTANCE_POOL_MANAGER)
+                .map(|manager| manager.id),
+            hostname: instance.name,
+            zone: zone.to_string(),
+            ipv4_address: instance.ipv4_address,
+            ipv6_address: instance.ipv6_address,
+            labels: instance.labels,
+            security_groups,
+            anti_affinity_groups,
+            instance_type,
+            template_id: instance.template.map(|template| template.id),
+            ready,
+            ..CloudInstance::default()
+        })
+    }
+
+    // Best-effort: API keys restricted to instances may not read networks, which
+    // are then left unconfigured
+    async fn get_network_attachments(
+        &self,
+        instance: &ExoscaleInstance,
+        zone: &str,
+    ) -> Vec<CloudNetworkAttachment> {
+        let mut networks = Vec::new();
+
+        for attachment in &instance.private_networks {
+            let path = format!("/private-network/{}", attachment.id);
+            let network: ExoscalePrivateNetwork = match self.api_client.get(zone, &path).await {
+                Ok(network) => network,
+                Err(error) => {
+                    warn!("Unable to get private network {}: {}", attachment.id, error);
+                    continue;
+                }
+            };
+
+            // Static leases take precedence over DHCP on managed networks
+            let lease = network
+                .leases
+                .iter()
+                .find(|lease| lease.instance_id == instance.id);
+            let prefix_length = network
+                .netmask
+                .as_deref()
+                .and_then(|netmask| netmask.parse::<Ipv4Addr>().ok())
+                .map(|netmask| u32::from(netmask).count_ones() as u8);
+            if lease.is_some() && prefix_length.is_none() {
+                warn!(
+                    "Private network {} has no valid netmask, leaving its static lease unconfigured",
+                    network.id
+                );
+            }
+
+            debug!(
+                "Found private network {} ({})",
+                network.id, attachment.mac_address
+            );
+            networks.push(CloudNetworkAttachment::PrivateNetwork {
+                id: network.id,
+                name: network.name,
+                mac_address: attachment.mac_address.clone(),
+                address: lease.map(|lease| lease.ip_address.clone()),
+                prefix_length: lease.and(prefix_length),
+                dhcp: lease.is_none() && network.start_ip.is_some(),
+            });
+        }
+
+        for attachment in &instance.elastic_ips {
+            let path = format!("/elastic-ip/{}", attachment.id);
+            let elastic_ip: ExoscaleElasticIp = match self.api_client.get(zone, &path).await {
+                Ok(elastic_ip) => elastic_ip,
+                Err(error) => {
+                    warn!("Unable to get elastic IP {}: {}", attachment.id, error);
+                    continue;
+                }
+            };
+
+            debug!("Found elastic IP {}", elastic_ip.ip);
+            networks.push(CloudNetworkAttachment::ElasticIp {
+                id: elastic_ip.id,
+                address: elastic_ip.ip,
+            });
+        }
+
+        networks
+    }
+
+    // Running, with the public addresses it was created with
+    fn is_ready(instance: &ExoscaleInstance) -> bool {
+        let has_ipv4 = instance.ipv4_address.is_some();
+        let has_ipv6 = instance.ipv6_address.is_some();
+
+        instance.state.as_deref() == Some(EXOSCALE_INSTANCE_RUNNING)
+            && match instance.public_ip_assignment.as_deref() {
+                Some("none") => true,
+                Some("dual") => has_ipv4 && has_ipv6,
+                _ => has_ipv4,
+            }
+    }
+
+    // Members are fetched concurrently. While polling, those already known to be
+    // ready may be taken from the cache filled by the previous calls, their labels
+    // being possibly outdated. Members that can't be fetched are left out.
+    async fn fetch_instance_group(
+        &self,
+        id: &str,
+        zone: &str,
+        use_cache: bool,
+    ) -> Result<CloudInstanceGroup, CloudProviderError> {
+        let path = format!("/instance-pool/{}", id);
+        let instance_pool: ExoscaleInstancePool = self.api_client.get(zone, &path).await?;
+
+        let instances = stream::iter(instance_pool.instances)
+            .map(|reference| async move {
+                let ready_instances = || {
+                    self.ready_instances
+                        .lock()
+                        .unwrap_or_else(PoisonError::into_inner)
+                };
+                if use_cache {
+                    if let Some(instance) = ready_instances().get(&reference.id).cloned() {
+                        return Some(instance);
+                    }
+                }
+
+                match self.get_instance(reference.id.as_str(), zone).await {
+                    Ok(instance) => {
+                        if instance.ready {
+                            ready_instances()
+                                .insert(instance.instance_id.clone(), instance.clone());
+                        }
+                        Some(instance)
+                    }
+                    Err(error) => {
+                        warn!(
+                            "Unable to get instance {} of pool {}: {}",
+                            reference.id, id, error
+                        );
+                        None
+                    }
+                }
+            })
+            .buffered(self.api_concurrency)
+            .filter_map(|instance| async move { instance })
+            .collect::<Vec<_>>()
+            .await;
+
+        Ok(CloudInstanceGroup {
+            instance_group_id: String::from(id),
+            instances,
+            size: instance_pool.size,
+        })
+    }
+
+    // Polls the pool until as many instances as its current size are ready, which
+    // also copes with pools scaled down while waiting. Failed lookups are retried
+    // on the next poll.
+    async fn wait_instance_group(
+        &self,
+        id: &str,
+        zone: &str,
+        options: &ExoscaleCloudProviderConfiguration,
+    ) -> Result<Option<CloudInstanceGroup>, CloudProviderError> {
+        info!("Waiting for all instances to be ready");
+        let timeout = Duration::from_secs(options.pool_wait_timeout_secs);
+        let delay = Duration::from_secs(options.api_retry_delay_secs);
+        let started = Instant::now();
+        let mut last_progress: Option<(usize, Instant)> = None;
+        let mut last_group: Option<CloudInstanceGroup> = None;
+        let mut use_cache = false;
+
+        loop {
+            let group = match use_cache {
+                true => self.fetch_instance_group(id, zone, true).await,
+                false => self.get_instance_group(id, zone).await,
+            };
+            match group {
+                Ok(mut group) => {
+                    group.instances.retain(|instance| instance.ready);
+                    let ready = group.instances.len();
+
+                    // The complete group is fetched again without the cache, for
+                    // up to date labels
+                    if ready >= group.size && use_cache {
+                        use_cache = false;
+                        continue;
+                    }
+
+                    if ready >= group.size {
+                        info!("All {} instances ready", group.size);
+                        return Ok(Some(group));
+                    }
+
+                    let report = match last_progress {
+                        Some((reported, at)) => {
+                            reported != ready
+                                || at.elapsed()
+                                    >= Duration::from_secs(EXOSCALE_POOL_PROGRESS_INTERVAL_SECS)
+                        }
+                        None => true,
+                    };
+                    if report {
+                        info!(
+                            "{}/{} instances ready after {}s",
+                            ready,
+                            group.size,
+                            started.elapsed().as_secs()
+                        );
+                        last_progress = Some((ready, Instant::now()));
+                    }
+
+                    last_group = Some(group);
+                }
+                Err(error) => warn!("Unable to get instance pool {}, retrying: {}", id, error),
+            }
+
+            if started.elapsed() + delay > timeout {
+                let reason = match &last_group {
+                    Some(group) => format!(
+                        "{}/{} instances ready after {}s",
+                        group.instances.len(),
+                        group.size,
+                        timeout.as_secs()
+                    ),
+                    None => format!("unable to get the pool after {}s", timeout.as_secs()),
+                };
+                return match (options.pool_wait_policy, last_group) {
+                    (PoolWaitPolicy::Partial, Some(group)) => {
+                        warn!(
+                            "Instance pool not ready ({}), continuing with ready instances",
+                            reason
+                        );
+                        // Cached members may have outdated labels
+                        let mut group = match use_cache {
+                            true => self.get_instance_group(id, zone).await.unwrap_or(group),
+                            false => group,
+                        };
+                        group.instances.retain(|instance| instance.ready);
+                        Ok(Some(group))
+                    }
+                    (PoolWaitPolicy::Continue | PoolWaitPolicy::Partial, _) => {
+                        warn!(
+                            "Instance pool not ready ({}), continuing without it",
+                            reason
+                        );
+                        Ok(None)
+                    }
+                    (PoolWaitPolicy::Fail, _) => Err(CloudProviderError::GroupNotReady(reason)),
+                };
+            }
+
+            tokio::time::sleep(delay).await;
+            use_cache = true;
+        }
+    }
+
+    pub async fn probe_advanced_instance_data(
+        &mut self,
+        configuration: &CloudConfiguration,
+        instance: &CloudInstance,
+    ) -> Result<(CloudInstance, Option<CloudInstanceGroup>), CloudProviderError> {
         info!("Configuring Exoscale API client");
-        let api_options = configuration.provider.exoscale.clone().ok_or_else(|| {
-            info!("Unable to get Exoscale provider configuration");
+        let api_options = configuration.provider.exoscale.clone().unwrap_or_default();
+        let credentials = load_credentials(&api_options).ok_or_else(|| {
+            info!("No Exoscale API credentials available");
             CloudProviderError::ConfigurationError
         })?;
 
-        self.set_api_credentials(ExoscaleAPICredentials {
-            api_key: api_options.api_key,
-            api_secret: api_options.api_secret,
-        });
+        self.set_api_credentials(credentials);
         self.set_api_timeout(api_options.api_timeout_secs);
+        self.set_api_concurrency(api_options.api_concurrency);
+        self.api_client
+            .set_max_body_size(api_options.api_max_body_size);
+        self.api_client
+            .set_tls(&api_options.api_tls)
+            .map_err(|err| {
+                error!("Unable to configure Exoscale API client: {}", err);
+                CloudProviderError::ConfigurationError
+            })?;
+        self.api_client.set_proxy(&api_options.api_proxy);
+        self.set_api_retry_policy(
+            api_options.api_retry_max_attempts,
+            api_options.api_retry_deadline_secs,
+        );
 
         info!("Loading instance data from API");
 
-        let instance = self
-            .get_instance(instance.instance_id.as_str(), instance.zone.as_str())
-            .await?;
+        let path = format!("/instance/{}", instance.instance_id);
+        let exoscale_instance: ExoscaleInstance =
+            self.api_client.get(&instance.zone, &path).await?;
+        let networks = self
+            .get_network_attachments(&exoscale_instance, &instance.zone)
+            .await;
+        let instance = CloudInstance {
+            networks,
+            ..self
+                .to_cloud_instance(exoscale_instance, &instance.zone)
+                .await?
+        };
 
-        if let Some(manager_id) = instance.manager_id.clone() {
-            let mut pool;
-            info!("Waiting for all instances to be ready");
-            loop {
-                pool = self
-                    .get_instance_group(manager_id.as_str(), instance.zone.as_str())
+        match instance.manager_id.clone() {
+            Some(manager_id) => {
+                let pool = self
+                    .wait_instance_group(&manager_id, &instance.zone, &api_options)
                     .await?;
-
-                match pool.instances.len() == pool.size {
-                    true => break,
-                    false => {
-                        debug!("Not yet fully available");
-                        tokio::time::sleep(std::time::Duration::from_secs(
-                            api_options.api_retry_delay_secs,
-                        ))
-                        .await;
-                    }
-                }
+                Ok((instance, pool))
             }
-
-            Ok((instance, Some(pool)))
-        } else {
-            Ok((instance, None))
+            None => Ok((instance, None)),
         }
     }
 }
 
 #[async_trait]
 impl CloudProvider for ExoscaleCloudProvider {
-    async fn probe(&mut self) -> Result<(CloudConfiguration, CloudInstance, Option<CloudInstanceGroup>), CloudProviderError> {
+    async fn probe(
+        &mut self,
+    ) -> Result<
+        (
+            CloudConfiguration,
+            CloudInstance,
+            Option<CloudInstanceGroup>,
+        ),
+        CloudProviderError,
+    > {
         debug!("Probing Exoscale cloud provider");
         if self.get_metadata_cloud_identifier().await? != EXOSCALE_CLOUD_IDENTIFIER {
             debug!("Not running in Exoscale cloud");
@@ -160,24 +562,39 @@ impl CloudProvider for ExoscaleCloudProvider {
 
         info!("Loading configuration from user_data");
         let user_data = self.get_metadata_userdata().await?;
-        let configuration = CloudConfiguration::from_str(user_data.as_str()).ok_or_else(|| {
-            error!("Unable to parse cloud configuration");
-            CloudProviderError::ConfigurationError
-        })?;
+        let configuration = CloudConfiguration::load(user_data.as_str())
+            .await
+            .ok_or_else(|| {
+                error!("Unable to parse cloud configuration");
+                CloudProviderError::ConfigurationError
+            })?;
 
         info!("Loading instance data from cloud metadata server");
         let instance = self.probe_basic_instance_data().await?;
 
-        let (instance, instance_group) = match self.probe_advanced_instance_data(&configuration, &instance).await {
+        // Without the API, whether the instance belongs to a pool is unknown
+        let unknown = CloudInstance {
+            membership_unknown: true,
+            ..instance.clone()
+        };
+        let (instance, instance_group) = match self
+            .probe_advanced_instance_data(&configuration, &instance)
+            .await
+        {
             Ok(instance_data) => instance_data,
-            _ => (instance, None)
+            Err(CloudProviderError::ConfigurationError) => (unknown, None),
+            Err(err @ CloudProviderError::GroupNotReady(_)) => return Err(err),
+            Err(err) => {
+                error!("Unable to load instance data from API: {}", err);
+                (unknown, None)
+            }
         };
 
         Ok((configuration, instance, instance_group))
     }
 
     async fn get_metadata_userdata(&self) -> Result<String, CloudProviderError> {
-        Ok(self.metadata_get("user-data").await?)
+        Ok(self.metadata_send("user-data", true).await?)
     }
 
     async fn get_metadata_cloud_identifier(&self) -> Result<String, CloudProviderError> {
@@ -201,17 +618,10 @@ impl CloudProvider for ExoscaleCloudProvider {
         id: &str,
         zone: &str,
     ) -> Result<CloudInstance, CloudProviderError> {
-        let path = format!("/instance-pool/{}", id);
-        let instance: ExoscaleInstance = self.api_get(zone, &path).await?;
+        let path = format!("/instance/{}", id);
+        let instance: ExoscaleInstance = self.api_client.get(zone, &path).await?;
 
-        Ok(CloudInstance {
-            instance_id: instance.id,
-            manager_id: instance.manager.map(|manager| manager.id),
-            hostname: instance.name,
-            zone: zone.to_string(),
-            ipv4_address: instance.ipv4_address,
-            ipv6_address: instance.ipv6_address,
-        })
+        self.to_cloud_instance(instance, zone).await
     }
 
     async fn get_instance_group(
@@ -219,21 +629,38 @@ impl CloudProvider for ExoscaleCloudProvider {
         id: &str,
         zone: &str,
     ) -> Result<CloudInstanceGroup, CloudProviderError> {
-        let path = format!("/instance-pool/{}", id);
-        let instance_pool: ExoscaleInstancePool = self.api_get(zone, &path).await?;
+        self.fetch_instance_group(id, zone, false).await
+    }
 
-        let mut instances = Vec::new();
-        for instance in instance_pool.instances {
-            instances.push(
-                self.get_instance(instance.id.as_str(), zone.as_ref())
-                    .await?,
-            );
+    // Labels are replaced as a whole, the known ones are sent along the new one
+    async fn set_instance_label(
+        &self,
+        instance: &CloudInstance,
+        name: &str,
+        value: &str,
+    ) -> Result<(), CloudProviderError> {
+        if instance.labels.get(name).map(String::as_str) == Some(value) {
+            return Ok(());
         }
 
-        Ok(CloudInstanceGroup {
-            instance_group_id: String::from(id),
-            instances,
-            size: instance_pool.size,
-        })
+        let mut labels = instance.labels.clone();
+        labels.insert(name.to_string(), value.to_string());
+
+        let path = format!("/instance/{}", instance.instance_id);
+        let body = json!({ "labels": labels });
+        let operation: ExoscaleOperation = self
+            .api_client
+            .request(Method::PUT, &instance.zone, &path, &[], Some(&body))
+            .await?;
+
+        self.api_client
+            .wait_operation(
+                &instance.zone,
+                operation,
+                Duration::from_secs(EXOSCALE_OPERATION_TIMEOUT_SECS),
+            )
+            .await?;
+
+        Ok(())
     }
 }
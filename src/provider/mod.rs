@@ -4,11 +4,43 @@ pub mod exoscale;
 use crate::configuration::CloudConfiguration;
 use async_trait::async_trait;
 use error::CloudProviderError;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::env;
 
+// Comma separated metadata endpoints, in order of preference, overriding the
+// provider's defaults (e.g. "http://[fd00:ec2::254],http://169.254.169.254")
+const METADATA_ENDPOINTS_ENV: &str = "INSTANCE_INIT_METADATA_ENDPOINTS";
+
+pub fn metadata_endpoints(defaults: &[&str]) -> Vec<String> {
+    let endpoints = env::var(METADATA_ENDPOINTS_ENV)
+        .unwrap_or_default()
+        .split(',')
+        .map(|endpoint| endpoint.trim().trim_end_matches('/').to_string())
+        .filter(|endpoint| !endpoint.is_empty())
+        .collect::<Vec<_>>();
+
+    match endpoints.is_empty() {
+        true => defaults
+            .iter()
+            .map(|endpoint| endpoint.to_string())
+            .collect(),
+        false => endpoints,
+    }
+}
 
 #[async_trait]
 pub trait CloudProvider {
-    async fn probe(&mut self) -> Result<(CloudConfiguration, CloudInstance, Option<CloudInstanceGroup>), CloudProviderError>;
+    async fn probe(
+        &mut self,
+    ) -> Result<
+        (
+            CloudConfiguration,
+            CloudInstance,
+            Option<CloudInstanceGroup>,
+        ),
+        CloudProviderError,
+    >;
 
     async fn get_metadata_userdata(&self) -> Result<String, CloudProviderError>;
     async fn get_metadata_cloud_identifier(&self) -> Result<String, CloudProviderError>;
@@ -24,9 +56,17 @@ pub trait CloudProvider {
         id: &str,
         zone: &str,
     ) -> Result<CloudInstanceGroup, CloudProviderError>;
+
+    // Publishes a value to the other members, e.g. a public key
+    async fn set_instance_label(
+        &self,
+        instance: &CloudInstance,
+        name: &str,
+        value: &str,
+    ) -> Result<(), CloudProviderError>;
 }
 
-#[derive(Clone, Debug, PartialEq, Default)]
+#[derive(Clone, Debug, PartialEq, Default, Serialize)]
 pub struct CloudInstance {
     pub instance_id: String,
     pub manager_id: Option<String>,
@@ -34,11 +74,173 @@ pub struct CloudInstance {
     pub zone: String,
     pub ipv4_address: Option<String>,
     pub ipv6_address: Option<String>,
+    pub networks: Vec<CloudNetworkAttachment>,
+    pub labels: HashMap<String, String>,
+    pub security_groups: Vec<String>,
+    pub anti_affinity_groups: Vec<String>,
+    pub instance_type: Option<String>,
+    pub template_id: Option<String>,
+    // Position in the instance group sorted by instance id, the first being the leader
+    pub ordinal: Option<usize>,
+    pub is_leader: bool,
+    // Running with its addresses assigned, as reported by the provider
+    #[serde(skip)]
+    pub ready: bool,
+    // Only known from the metadata server, the provider API being unavailable:
+    // it may as well be a member of a group
+    #[serde(skip)]
+    pub membership_unknown: bool,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum CloudNetworkAttachment {
+    // Without an address nor DHCP, the interface is only brought up
+    PrivateNetwork {
+        id: String,
+        name: Option<String>,
+        mac_address: String,
+        address: Option<String>,
+        prefix_length: Option<u8>,
+        dhcp: bool,
+    },
+    // Answered for on the host, routing being done by the platform
+    ElasticIp {
+        id: String,
+        address: String,
+    },
 }
 
-#[derive(Clone, Debug, PartialEq, Default)]
+// Public address of the instances used to reach them, e.g. between cluster members
+#[derive(Clone, Copy, Deserialize, Debug, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum AddressFamily {
+    #[default]
+    Ipv4,
+    Ipv6,
+}
+
+impl AddressFamily {
+    pub fn address<'a>(&self, instance: &'a CloudInstance) -> Option<&'a str> {
+        match self {
+            AddressFamily::Ipv4 => instance.ipv4_address.as_deref(),
+            AddressFamily::Ipv6 => instance.ipv6_address.as_deref(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Default, Serialize)]
 pub struct CloudInstanceGroup {
     pub instance_group_id: String,
     pub instances: Vec<CloudInstance>,
     pub size: usize,
 }
+
+// The leader is the member with the lowest instance id, so that every member
+// elects the same one without coordination. A standalone instance leads itself,
+// while a pool member whose group couldn't be resolved, or an instance whose
+// membership is unknown, neither leads nor gets an ordinal, to avoid several
+// members taking the lead.
+pub fn elect_leader(instance: &mut CloudInstance, group: Option<&mut CloudInstanceGroup>) {
+    let group = match group {
+        Some(group) => group,
+        None => {
+            let standalone = instance.manager_id.is_none() && !instance.membership_unknown;
+            instance.ordinal = standalone.then_some(0);
+            instance.is_leader = standalone;
+            return;
+        }
+    };
+
+    group
+        .instances
+        .sort_by(|a, b| a.instance_id.cmp(&b.instance_id));
+    for (ordinal, member) in group.instances.iter_mut().enumerate() {
+        member.ordinal = Some(ordinal);
+        member.is_leader = ordinal == 0;
+    }
+
+    let member = group
+        .instances
+        .iter()
+        .find(|member| member.instance_id == instance.instance_id);
+    instance.ordinal = member.and_then(|member| member.ordinal);
+    instance.is_leader = member.is_some_and(|member| member.is_leader);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn instance(instance_id: &str, manager_id: Option<&str>) -> CloudInstance {
+        CloudInstance {
+            instance_id: instance_id.to_string(),
+            manager_id: manager_id.map(String::from),
+            ..CloudInstance::default()
+        }
+    }
+
+    fn group(instance_ids: &[&str]) -> CloudInstanceGroup {
+        CloudInstanceGroup {
+            instance_group_id: "pool".to_string(),
+            instances: instance_ids
+                .iter()
+                .map(|instance_id| instance(instance_id, Some("pool")))
+                .collect(),
+            size: instance_ids.len(),
+        }
+    }
+
+    #[test]
+    fn standalone_instance_leads_itself() {
+        let mut standalone = instance("a", None);
+        elect_leader(&mut standalone, None);
+
+        assert_eq!(standalone.ordinal, Some(0));
+        assert!(standalone.is_leader);
+    }
+
+    #[test]
+    fn member_gets_its_ordinal_in_the_group() {
+        let mut group = group(&["c", "a", "b"]);
+        let mut member = instance("b", Some("pool"));
+        elect_leader(&mut member, Some(&mut group));
+
+        assert_eq!(member.ordinal, Some(1));
+        assert!(!member.is_leader);
+        let leaders = group
+            .instances
+            .iter()
+            .filter(|member| member.is_leader)
+            .map(|member| member.instance_id.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(leaders, ["a"]);
+
+        let mut leader = instance("a", Some("pool"));
+        elect_leader(&mut leader, Some(&mut group));
+        assert_eq!(leader.ordinal, Some(0));
+        assert!(leader.is_leader);
+    }
+
+    #[test]
+    fn member_without_group_does_not_lead() {
+        let mut member = instance("a", Some("pool"));
+        elect_leader(&mut member, None);
+
+        assert_eq!(member.ordinal, None);
+        assert!(!member.is_leader);
+    }
+
+    #[test]
+    fn instance_with_unknown_membership_does_not_lead() {
+        // As probed when the provider API can't be queried
+        let mut unknown = CloudInstance {
+            membership_unknown: true,
+            ..instance("a", None)
+        };
+        elect_leader(&mut unknown, None);
+
+        assert_eq!(unknown.ordinal, None);
+        assert!(!unknown.is_leader);
+    }
+}
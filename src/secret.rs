@@ -0,0 +1,69 @@
+use serde::{Deserialize, Deserializer};
+use std::fmt;
+use std::sync::Mutex;
+
+const REDACTED: &str = "***";
+
+// Every secret loaded from configuration, to scrub them from logged text
+static REGISTERED_SECRETS: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+pub fn register(value: &str) {
+    if value.is_empty() {
+        return;
+    }
+
+    if let Ok(mut secrets) = REGISTERED_SECRETS.lock() {
+        if !secrets.iter().any(|secret| secret == value) {
+            secrets.push(value.to_string());
+        }
+    }
+}
+
+// Masks every known secret value in a text about to be logged
+pub fn redact(text: &str) -> String {
+    let mut redacted = text.to_string();
+    if let Ok(secrets) = REGISTERED_SECRETS.lock() {
+        for secret in secrets.iter() {
+            redacted = redacted.replace(secret.as_str(), REDACTED);
+        }
+    }
+    redacted
+}
+
+// A configuration value that must never end up in logs
+#[derive(Clone, PartialEq, Default)]
+pub struct Secret(String);
+
+impl Secret {
+    pub fn new(value: String) -> Self {
+        register(value.as_str());
+        Self(value)
+    }
+
+    pub fn expose(&self) -> &str {
+        self.0.as_str()
+    }
+}
+
+impl<'de> Deserialize<'de> for Secret {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let value = String::deserialize(deserializer)?;
+        register(value.as_str());
+        Ok(Self(value))
+    }
+}
+
+impl fmt::Debug for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Secret({})", REDACTED)
+    }
+}
+
+impl fmt::Display for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", REDACTED)
+    }
+}
@@ -0,0 +1,325 @@
+use serde_json::Value as Fact;
+use std::fmt;
+use toml::Value;
+
+// Minimal templating of configuration values with instance facts.
+//
+// Expressions are written `{{ path }}` where path is a dotted lookup into the
+// facts, e.g. `instance.hostname`, `group.instances[0].hostname` or
+// `group.instances[*].ipv4_address` (projection over every element).
+//
+// When a string value is made of a single expression, the value keeps the
+// type of the fact (a projection yields a TOML array). Otherwise each
+// expression is interpolated as text, arrays being joined with a comma.
+
+const EXPRESSION_START: &str = "{{";
+const EXPRESSION_END: &str = "}}";
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum TemplateError {
+    SyntaxError(String),
+    UnknownVariable(String),
+}
+
+impl fmt::Display for TemplateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TemplateError::SyntaxError(expression) => {
+                write!(f, "invalid expression `{}`", expression)
+            }
+            TemplateError::UnknownVariable(expression) => {
+                write!(f, "unknown variable `{}`", expression)
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+enum Segment {
+    Field(String),
+    Index(usize),
+    Wildcard,
+}
+
+fn parse_expression(expression: &str) -> Result<Vec<Segment>, TemplateError> {
+    let syntax_error = || TemplateError::SyntaxError(expression.to_string());
+    let mut segments = Vec::new();
+
+    for part in expression.split('.') {
+        let (name, mut indexes) = match part.find('[') {
+            Some(position) => part.split_at(position),
+            None => (part, ""),
+        };
+
+        if name.is_empty()
+            || !name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+        {
+            return Err(syntax_error());
+        }
+        segments.push(Segment::Field(name.to_string()));
+
+        while !indexes.is_empty() {
+            let end = indexes.find(']').ok_or_else(syntax_error)?;
+            let index = indexes[1..end].trim();
+            segments.push(match index {
+                "*" => Segment::Wildcard,
+                _ => Segment::Index(index.parse().map_err(|_| syntax_error())?),
+            });
+
+            indexes = &indexes[end + 1..];
+            if !indexes.is_empty() && !indexes.starts_with('[') {
+                return Err(syntax_error());
+            }
+        }
+    }
+
+    Ok(segments)
+}
+
+fn resolve(fact: &Fact, segments: &[Segment], expression: &str) -> Result<Fact, TemplateError> {
+    let unknown = || TemplateError::UnknownVariable(expression.to_string());
+
+    let (segment, rest) = match segments.split_first() {
+        Some(split) => split,
+        None => return Ok(fact.clone()),
+    };
+
+    // Only a whole fact may be missing, not one it should contain
+    if fact.is_null() {
+        return Err(unknown());
+    }
+
+    match segment {
+        Segment::Field(name) => {
+            let fact = fact
+                .as_object()
+                .and_then(|object| object.get(name))
+                .ok_or_else(unknown)?;
+            resolve(fact, rest, expression)
+        }
+        Segment::Index(index) => {
+            let fact = fact
+                .as_array()
+                .and_then(|array| array.get(*index))
+                .ok_or_else(unknown)?;
+            resolve(fact, rest, expression)
+        }
+        Segment::Wildcard => {
+            let facts = fact.as_array().ok_or_else(unknown)?;
+            Ok(Fact::Array(
+                facts
+                    .iter()
+                    .map(|fact| resolve(fact, rest, expression))
+                    .collect::<Result<Vec<_>, _>>()?,
+            ))
+        }
+    }
+}
+
+fn evaluate(expression: &str, facts: &Fact) -> Result<Fact, TemplateError> {
+    let expression = expression.trim();
+    let segments = parse_expression(expression)?;
+
+    // Missing root facts (e.g. the group of a standalone instance) render empty,
+    // projections over them being empty lists
+    if let Some(Segment::Field(name)) = segments.first() {
+        if facts.get(name).is_some_and(Fact::is_null) {
+            let projection = segments
+                .iter()
+                .any(|segment| matches!(segment, Segment::Wildcard));
+            return Ok(match projection {
+                true => Fact::Array(Vec::new()),
+                false => Fact::Null,
+            });
+        }
+    }
+
+    resolve(facts, &segments, expression)
+}
+
+fn fact_to_string(fact: &Fact) -> String {
+    match fact {
+        Fact::Null => String::new(),
+        Fact::String(value) => value.clone(),
+        Fact::Array(values) => values
+            .iter()
+            .map(fact_to_string)
+            .collect::<Vec<_>>()
+            .join(","),
+        other => other.to_string(),
+    }
+}
+
+fn fact_to_value(fact: Fact) -> Value {
+    match fact {
+        Fact::Null => Value::String(String::new()),
+        Fact::Bool(value) => Value::Boolean(value),
+        Fact::Number(value) => match value.as_i64() {
+            Some(value) => Value::Integer(value),
+            None => Value::Float(value.as_f64().unwrap_or_default()),
+        },
+        Fact::String(value) => Value::String(value),
+        Fact::Array(values) => Value::Array(values.into_iter().map(fact_to_value).collect()),
+        Fact::Object(values) => Value::Table(
+            values
+                .into_iter()
+                .map(|(key, value)| (key, fact_to_value(value)))
+                .collect(),
+        ),
+    }
+}
+
+fn render_string(template: &str, facts: &Fact) -> Result<Value, TemplateError> {
+    let trimmed = template.trim();
+    if trimmed.starts_with(EXPRESSION_START)
+        && trimmed.ends_with(EXPRESSION_END)
+        && trimmed.matches(EXPRESSION_START).count() == 1
+    {
+        let expression = &trimmed[EXPRESSION_START.len()..trimmed.len() - EXPRESSION_END.len()];
+        return Ok(fact_to_value(evaluate(expression, facts)?));
+    }
+
+    let mut rendered = String::with_capacity(template.len());
+    let mut remaining = template;
+    while let Some(start) = remaining.find(EXPRESSION_START) {
+        rendered.push_str(&remaining[..start]);
+        remaining = &remaining[start + EXPRESSION_START.len()..];
+
+        let end = remaining
+            .find(EXPRESSION_END)
+            .ok_or_else(|| TemplateError::SyntaxError(template.to_string()))?;
+        rendered.push_str(&fact_to_string(&evaluate(&remaining[..end], facts)?));
+        remaining = &remaining[end + EXPRESSION_END.len()..];
+    }
+    rendered.push_str(remaining);
+
+    Ok(Value::String(rendered))
+}
+
+pub fn render(value: &Value, facts: &Fact) -> Result<Value, TemplateError> {
+    Ok(match value {
+        Value::String(template) if template.contains(EXPRESSION_START) => {
+            render_string(template, facts)?
+        }
+        Value::Array(values) => {
+            let mut rendered = Vec::with_capacity(values.len());
+            for value in values {
+                // Projections inside an array are spliced into it
+                match (value, render(value, facts)?) {
+                    (Value::String(_), Value::Array(projected)) => rendered.extend(projected),
+                    (_, value) => rendered.push(value),
+                }
+            }
+            Value::Array(rendered)
+        }
+        Value::Table(values) => Value::Table(
+            values
+                .iter()
+                .map(|(key, value)| Ok((key.clone(), render(value, facts)?)))
+                .collect::<Result<_, TemplateError>>()?,
+        ),
+        other => other.clone(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn facts(group: Fact) -> Fact {
+        json!({
+            "instance": { "hostname": "web-1", "zone": "ch-gva-2", "ordinal": 1 },
+            "group": group,
+        })
+    }
+
+    fn group() -> Fact {
+        json!({
+            "size": 2,
+            "instances": [
+                { "hostname": "web-0", "ipv4_address": "192.0.2.10" },
+                { "hostname": "web-1", "ipv4_address": "192.0.2.11" },
+            ],
+        })
+    }
+
+    fn render_toml(template: &str, facts: &Fact) -> Result<Value, TemplateError> {
+        render(&toml::from_str(template).unwrap(), facts)
+    }
+
+    #[test]
+    fn interpolates_expressions() {
+        let rendered = render_toml(
+            r#"
+            name = "{{ instance.hostname }}.{{ instance.zone }}"
+            ordinal = "{{ instance.ordinal }}"
+            first = "node {{group.instances[0].hostname}}"
+            "#,
+            &facts(group()),
+        )
+        .unwrap();
+
+        assert_eq!(rendered["name"].as_str(), Some("web-1.ch-gva-2"));
+        assert_eq!(rendered["ordinal"].as_integer(), Some(1));
+        assert_eq!(rendered["first"].as_str(), Some("node web-0"));
+    }
+
+    #[test]
+    fn projects_over_every_element() {
+        let rendered = render_toml(
+            r#"
+            addresses = ["127.0.0.1", "{{ group.instances[*].ipv4_address }}"]
+            joined = "peers={{ group.instances[*].hostname }}"
+            "#,
+            &facts(group()),
+        )
+        .unwrap();
+
+        assert_eq!(
+            rendered["addresses"],
+            toml::from_str::<Value>(r#"v = ["127.0.0.1", "192.0.2.10", "192.0.2.11"]"#).unwrap()
+                ["v"]
+        );
+        assert_eq!(rendered["joined"].as_str(), Some("peers=web-0,web-1"));
+    }
+
+    #[test]
+    fn rejects_unknown_facts() {
+        for template in [
+            r#"v = "{{ instance.unknown }}""#,
+            r#"v = "{{ group.instances[5].hostname }}""#,
+            r#"v = "{{ group.instances[*].unknown }}""#,
+            r#"v = "{{ unknown }}""#,
+        ] {
+            assert!(matches!(
+                render_toml(template, &facts(group())),
+                Err(TemplateError::UnknownVariable(_))
+            ));
+        }
+
+        assert!(matches!(
+            render_toml(r#"v = "{{ instance..hostname }}""#, &facts(group())),
+            Err(TemplateError::SyntaxError(_))
+        ));
+    }
+
+    #[test]
+    fn missing_group_renders_empty() {
+        let rendered = render_toml(
+            r#"
+            addresses = ["{{ group.instances[*].ipv4_address }}"]
+            size = "{{ group.size }}"
+            joined = "peers={{ group.instances[*].hostname }}"
+            "#,
+            &facts(Fact::Null),
+        )
+        .unwrap();
+
+        assert_eq!(rendered["addresses"].as_array(), Some(&Vec::new()));
+        assert_eq!(rendered["size"].as_str(), Some(""));
+        assert_eq!(rendered["joined"].as_str(), Some("peers="));
+    }
+}
@@ -0,0 +1,128 @@
+use crate::host;
+use crate::provider::CloudInstance;
+use crate::tls::acme::jws::base64url;
+use crate::tls::TlsError;
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Request, Response, Server, StatusCode};
+use log::{debug, info};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use tokio::sync::oneshot;
+use tokio::task::JoinHandle;
+
+const HTTP_CHALLENGE_PATH: &str = "/.well-known/acme-challenge/";
+const DNS_CHALLENGE_PREFIX: &str = "_acme-challenge";
+
+// Key authorizations by token
+type Responses = Arc<Mutex<HashMap<String, String>>>;
+
+// Answers HTTP-01 challenges until stopped, only while the order is being validated
+pub struct HttpChallengeServer {
+    responses: Responses,
+    shutdown: oneshot::Sender<()>,
+    task: JoinHandle<()>,
+}
+
+impl HttpChallengeServer {
+    pub fn start(listen: &str) -> Result<Self, TlsError> {
+        let address = listen.parse::<SocketAddr>().map_err(|err| {
+            TlsError::AcmeError(format!("invalid listen address {}: {}", listen, err))
+        })?;
+
+        let responses = Responses::default();
+        let shared = responses.clone();
+        let make_service = make_service_fn(move |_| {
+            let responses = shared.clone();
+            async move {
+                Ok::<_, Infallible>(service_fn(move |request: Request<Body>| {
+                    let response = respond(&responses, &request);
+                    async move { Ok::<_, Infallible>(response) }
+                }))
+            }
+        });
+
+        let server = Server::try_bind(&address)
+            .map_err(|err| {
+                TlsError::AcmeError(format!("unable to listen on {}: {}", address, err))
+            })?
+            .serve(make_service);
+
+        let (shutdown, stopped) = oneshot::channel::<()>();
+        let task = tokio::spawn(async move {
+            let server = server.with_graceful_shutdown(async {
+                stopped.await.ok();
+            });
+            if let Err(error) = server.await {
+                debug!("ACME challenge server failed: {}", error);
+            }
+        });
+        info!("Answering ACME HTTP-01 challenges on {}", address);
+
+        Ok(Self {
+            responses,
+            shutdown,
+            task,
+        })
+    }
+
+    pub fn add(&self, token: &str, key_authorization: &str) {
+        self.responses
+            .lock()
+            .unwrap()
+            .insert(token.to_string(), key_authorization.to_string());
+    }
+
+    pub async fn stop(self) {
+        self.shutdown.send(()).ok();
+        self.task.await.ok();
+    }
+}
+
+fn respond(responses: &Responses, request: &Request<Body>) -> Response<Body> {
+    let path = request.uri().path();
+    debug!("ACME challenge request for {}", path);
+
+    let key_authorization = path
+        .strip_prefix(HTTP_CHALLENGE_PATH)
+        .and_then(|token| responses.lock().unwrap().get(token).cloned());
+
+    let (status, body) = match key_authorization {
+        Some(key_authorization) => (StatusCode::OK, key_authorization),
+        None => (StatusCode::NOT_FOUND, String::new()),
+    };
+
+    let mut response = Response::new(Body::from(body));
+    *response.status_mut() = status;
+    response
+}
+
+// DNS-01 challenges are published by a hook, which gets the record to create
+// or remove in its environment
+pub fn run_dns_hook(
+    command: &str,
+    instance: &CloudInstance,
+    action: &str,
+    domain: &str,
+    key_authorization: &str,
+) -> Result<(), TlsError> {
+    // The challenge of a wildcard name is the one of its base domain
+    let domain = domain.trim_start_matches("*.");
+    let record_name = format!("{}.{}", DNS_CHALLENGE_PREFIX, domain);
+    let record_value = base64url(&Sha256::digest(key_authorization.as_bytes()));
+
+    host::run_hook_with_env(
+        command,
+        instance,
+        &[
+            ("ACME_ACTION", action),
+            ("ACME_DOMAIN", domain),
+            ("ACME_RECORD_NAME", &record_name),
+            ("ACME_RECORD_VALUE", &record_value),
+        ],
+    )?;
+
+    Ok(())
+}
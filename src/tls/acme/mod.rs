@@ -0,0 +1,937 @@
+mod challenge;
+mod jws;
+
+use crate::http_client::{HttpClient, HttpError, HttpRequest, HttpResponse, TlsConfiguration};
+use crate::provider::CloudInstance;
+use crate::secret::Secret;
+use crate::tls::{self, CertificateFiles, IssuedCertificate, TlsError};
+use challenge::{run_dns_hook, HttpChallengeServer};
+use hyper::Method;
+use jws::{base64url, AccountKey};
+use log::{debug, error, info};
+use rcgen::{Certificate, CertificateParams, DistinguishedName, DnType};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::net::IpAddr;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+const CONTENT_TYPE_JOSE: &str = "application/jose+json";
+const REPLAY_NONCE_HEADER: &str = "replay-nonce";
+const LOCATION_HEADER: &str = "location";
+const BAD_NONCE_ERROR: &str = "urn:ietf:params:acme:error:badNonce";
+
+const STATUS_PENDING: &str = "pending";
+const STATUS_READY: &str = "ready";
+const STATUS_VALID: &str = "valid";
+const STATUS_INVALID: &str = "invalid";
+
+const HTTP_01: &str = "http-01";
+const DNS_01: &str = "dns-01";
+
+const POLL_INTERVAL_SECS: u64 = 2;
+
+const PEM_CERTIFICATE_END: &str = "-----END CERTIFICATE-----";
+
+#[derive(Clone, Copy, Deserialize, Debug, Default, PartialEq)]
+pub enum AcmeChallengeType {
+    #[default]
+    #[serde(rename = "http-01")]
+    Http01,
+    #[serde(rename = "dns-01")]
+    Dns01,
+}
+
+#[derive(Clone, Deserialize, Debug)]
+pub struct AcmeConfiguration {
+    #[serde(default = "default_directory_url")]
+    pub directory_url: String,
+    // e.g. "admin@example.com", registered with the account
+    #[serde(default)]
+    pub contact: Vec<String>,
+    // Agreement to the terms of service of the CA, required by most of them
+    #[serde(default)]
+    pub terms_of_service_agreed: bool,
+    #[serde(default)]
+    pub names: Vec<String>,
+    // Adds the public addresses of the instance as IP identifiers, which few CAs accept
+    #[serde(default)]
+    pub include_addresses: bool,
+    // IP identifiers are always validated with HTTP-01
+    #[serde(default)]
+    pub challenge: AcmeChallengeType,
+    #[serde(default = "default_http_listen")]
+    pub http_listen: String,
+    // Creates (ACME_ACTION=present) or removes (ACME_ACTION=cleanup) the DNS-01 records
+    pub dns_hook: Option<String>,
+    #[serde(default = "default_dns_propagation_secs")]
+    pub dns_propagation_secs: u64,
+    #[serde(default = "default_validation_timeout_secs")]
+    pub validation_timeout_secs: u64,
+    #[serde(default = "default_directory")]
+    pub directory: String,
+    #[serde(default = "default_account_key_file")]
+    pub account_key_file: String,
+    #[serde(flatten)]
+    pub files: CertificateFiles,
+    #[serde(default = "default_timeout_secs")]
+    pub timeout_secs: u64,
+    #[serde(default)]
+    pub tls: TlsConfiguration,
+}
+
+fn default_directory_url() -> String {
+    "https://acme-v02.api.letsencrypt.org/directory".to_string()
+}
+
+fn default_http_listen() -> String {
+    "[::]:80".to_string()
+}
+
+fn default_dns_propagation_secs() -> u64 {
+    60
+}
+
+fn default_validation_timeout_secs() -> u64 {
+    120
+}
+
+fn default_directory() -> String {
+    "/var/lib/instance-init/tls/acme".to_string()
+}
+
+fn default_account_key_file() -> String {
+    "account.key".to_string()
+}
+
+fn default_timeout_secs() -> u64 {
+    30
+}
+
+// A small subset of the ACME resources
+// REF: https://www.rfc-editor.org/rfc/rfc8555
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct AcmeDirectory {
+    new_nonce: String,
+    new_account: String,
+    new_order: String,
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug)]
+struct AcmeIdentifier {
+    #[serde(rename = "type")]
+    identifier_type: String,
+    value: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct AcmeOrder {
+    status: String,
+    #[serde(default)]
+    authorizations: Vec<String>,
+    finalize: String,
+    certificate: Option<String>,
+    error: Option<Value>,
+}
+
+#[derive(Deserialize, Debug)]
+struct AcmeChallenge {
+    #[serde(rename = "type")]
+    challenge_type: String,
+    url: String,
+    #[serde(default)]
+    token: String,
+    error: Option<Value>,
+}
+
+#[derive(Deserialize, Debug)]
+struct AcmeAuthorization {
+    identifier: AcmeIdentifier,
+    status: String,
+    #[serde(default)]
+    challenges: Vec<AcmeChallenge>,
+}
+
+fn acme_error(reason: impl Into<String>) -> TlsError {
+    TlsError::AcmeError(reason.into())
+}
+
+fn location(response: &HttpResponse) -> Result<String, TlsError> {
+    response
+        .headers
+        .get(LOCATION_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .map(String::from)
+        .ok_or_else(|| acme_error(format!("no location returned by {}", response.url)))
+}
+
+struct AcmeClient {
+    http_client: HttpClient,
+    key: AccountKey,
+    directory: AcmeDirectory,
+    nonce: Option<String>,
+    account_url: Option<String>,
+}
+
+impl AcmeClient {
+    async fn new(configuration: &AcmeConfiguration, key: AccountKey) -> Result<Self, TlsError> {
+        let mut http_client = HttpClient::new(configuration.timeout_secs);
+        http_client.set_tls(&configuration.tls)?;
+
+        let directory = http_client
+            .send_json(HttpRequest::get(configuration.directory_url.clone()))
+            .await?;
+
+        Ok(Self {
+            http_client,
+            key,
+            directory,
+            nonce: None,
+            account_url: None,
+        })
+    }
+
+    // Every response carries the nonce of the next request
+    fn keep_nonce(&mut self, response: &HttpResponse) {
+        self.nonce = response
+            .headers
+            .get(REPLAY_NONCE_HEADER)
+            .and_then(|value| value.to_str().ok())
+            .map(String::from);
+    }
+
+    async fn nonce(&mut self) -> Result<String, TlsError> {
+        if let Some(nonce) = self.nonce.take() {
+            return Ok(nonce);
+        }
+
+        let request = HttpRequest::new(Method::HEAD, self.directory.new_nonce.clone());
+        let response = self.http_client.send(request).await?;
+        self.keep_nonce(&response);
+        self.nonce
+            .take()
+            .ok_or_else(|| acme_error("no nonce returned by the server"))
+    }
+
+    // Nonces may expire on the server side: a rejected one is replaced once
+    async fn post(&mut self, url: &str, payload: Option<&Value>) -> Result<HttpResponse, TlsError> {
+        let mut retried = false;
+
+        loop {
+            let nonce = self.nonce().await?;
+            let body = self
+                .key
+                .sign(url, &nonce, self.account_url.as_deref(), payload)?;
+            let request = HttpRequest::new(Method::POST, url.to_string())
+                .header("content-type", CONTENT_TYPE_JOSE)
+                .body(body);
+
+            match self.http_client.send(request).await {
+                Ok(response) => {
+                    self.keep_nonce(&response);
+                    return Ok(response);
+                }
+                Err(HttpError::ClientError { body, .. })
+                    if !retried && body.contains(BAD_NONCE_ERROR) =>
+                {
+                    debug!("ACME nonce rejected, retrying with a new one");
+                    retried = true;
+                }
+                Err(error) => return Err(error.into()),
+            }
+        }
+    }
+
+    async fn get<T: DeserializeOwned>(&mut self, url: &str) -> Result<T, TlsError> {
+        Ok(self.post(url, None).await?.json()?)
+    }
+
+    // Creates the account of the key, or finds the existing one
+    async fn register(
+        &mut self,
+        contact: &[String],
+        terms_of_service_agreed: bool,
+    ) -> Result<(), TlsError> {
+        let contact = contact
+            .iter()
+            .map(|contact| match contact.starts_with("mailto:") {
+                true => contact.clone(),
+                false => format!("mailto:{}", contact),
+            })
+            .collect::<Vec<_>>();
+        let payload =
+            json!({ "termsOfServiceAgreed": terms_of_service_agreed, "contact": contact });
+
+        let url = self.directory.new_account.clone();
+        let response = self.post(&url, Some(&payload)).await?;
+        let account_url = location(&response)?;
+        debug!("Using ACME account {}", account_url);
+        self.account_url = Some(account_url);
+
+        Ok(())
+    }
+
+    async fn new_order(
+        &mut self,
+        identifiers: &[AcmeIdentifier],
+    ) -> Result<(String, AcmeOrder), TlsError> {
+        let url = self.directory.new_order.clone();
+        let response = self
+            .post(&url, Some(&json!({ "identifiers": identifiers })))
+            .await?;
+
+        Ok((location(&response)?, response.json()?))
+    }
+
+    async fn wait_authorization(&mut self, url: &str, deadline: Instant) -> Result<(), TlsError> {
+        loop {
+            let authorization: AcmeAuthorization = self.get(url).await?;
+            match authorization.status.as_str() {
+                STATUS_VALID => return Ok(()),
+                STATUS_PENDING if Instant::now() < deadline => {}
+                status => {
+                    let reason = authorization
+                        .challenges
+                        .iter()
+                        .find_map(|challenge| challenge.error.as_ref())
+                        .map(|error| error.to_string())
+                        .unwrap_or_else(|| format!("authorization {}", status));
+                    return Err(acme_error(format!(
+                        "validation of {} failed: {}",
+                        authorization.identifier.value, reason
+                    )));
+                }
+            }
+
+            tokio::time::sleep(Duration::from_secs(POLL_INTERVAL_SECS)).await;
+        }
+    }
+
+    async fn wait_order(
+        &mut self,
+        url: &str,
+        status: &str,
+        deadline: Instant,
+    ) -> Result<AcmeOrder, TlsError> {
+        loop {
+            let order: AcmeOrder = self.get(url).await?;
+            if order.status == status {
+                return Ok(order);
+            }
+
+            if order.status == STATUS_INVALID || Instant::now() >= deadline {
+                let reason = order
+                    .error
+                    .map(|error| error.to_string())
+                    .unwrap_or_else(|| format!("order still {}", order.status));
+                return Err(acme_error(reason));
+            }
+
+            tokio::time::sleep(Duration::from_secs(POLL_INTERVAL_SECS)).await;
+        }
+    }
+}
+
+fn identifiers(configuration: &AcmeConfiguration, instance: &CloudInstance) -> Vec<AcmeIdentifier> {
+    let mut values = configuration.names.clone();
+    if configuration.include_addresses {
+        values.extend(instance.ipv4_address.iter().cloned());
+        values.extend(instance.ipv6_address.iter().cloned());
+    }
+
+    let mut identifiers: Vec<AcmeIdentifier> = Vec::new();
+    for value in values {
+        if identifiers
+            .iter()
+            .any(|identifier| identifier.value == value)
+        {
+            continue;
+        }
+
+        let identifier_type = match value.parse::<IpAddr>() {
+            Ok(_) => "ip",
+            Err(_) => "dns",
+        };
+        identifiers.push(AcmeIdentifier {
+            identifier_type: identifier_type.to_string(),
+            value,
+        });
+    }
+
+    identifiers
+}
+
+fn challenge_type(configuration: &AcmeConfiguration, identifier: &AcmeIdentifier) -> &'static str {
+    match (configuration.challenge, identifier.identifier_type.as_str()) {
+        (AcmeChallengeType::Dns01, "dns") => DNS_01,
+        _ => HTTP_01,
+    }
+}
+
+// Published DNS-01 challenges, to clean them up once the order is validated
+struct DnsChallenge {
+    domain: String,
+    key_authorization: String,
+}
+
+async fn respond_challenges(
+    configuration: &AcmeConfiguration,
+    instance: &CloudInstance,
+    client: &mut AcmeClient,
+    authorizations: &[(String, AcmeAuthorization)],
+    http_server: Option<&HttpChallengeServer>,
+    dns_challenges: &mut Vec<DnsChallenge>,
+    deadline: Instant,
+) -> Result<(), TlsError> {
+    let mut challenges = Vec::new();
+
+    for (url, authorization) in authorizations {
+        let identifier = &authorization.identifier;
+        let challenge_type = challenge_type(configuration, identifier);
+        let challenge = authorization
+            .challenges
+            .iter()
+            .find(|challenge| challenge.challenge_type == challenge_type)
+            .ok_or_else(|| {
+                acme_error(format!(
+                    "no {} challenge offered for {}",
+                    challenge_type, identifier.value
+                ))
+            })?;
+        let key_authorization = format!("{}.{}", challenge.token, client.key.thumbprint());
+
+        match (challenge_type, http_server, &configuration.dns_hook) {
+            (HTTP_01, Some(http_server), _) => {
+                http_server.add(&challenge.token, &key_authorization)
+            }
+            (DNS_01, _, Some(dns_hook)) => {
+                run_dns_hook(
+                    dns_hook,
+                    instance,
+                    "present",
+                    &identifier.value,
+                    &key_authorization,
+                )?;
+                dns_challenges.push(DnsChallenge {
+                    domain: identifier.value.clone(),
+                    key_authorization,
+                });
+            }
+            _ => {
+                return Err(acme_error(format!(
+                    "unable to answer {} challenges",
+                    challenge_type
+                )))
+            }
+        }
+
+        challenges.push((url.as_str(), challenge.url.as_str()));
+    }
+
+    if !dns_challenges.is_empty() {
+        info!(
+            "Waiting {}s for DNS records to propagate",
+            configuration.dns_propagation_secs
+        );
+        tokio::time::sleep(Duration::from_secs(configuration.dns_propagation_secs)).await;
+    }
+
+    for (_, challenge_url) in &challenges {
+        client.post(challenge_url, Some(&json!({}))).await?;
+    }
+
+    for (authorization_url, _) in &challenges {
+        client
+            .wait_authorization(authorization_url, deadline)
+            .await?;
+    }
+
+    Ok(())
+}
+
+// Answers the challenges of the pending authorizations, the listener and DNS
+// records only living as long as the validation
+async fn authorize(
+    configuration: &AcmeConfiguration,
+    instance: &CloudInstance,
+    client: &mut AcmeClient,
+    order: &AcmeOrder,
+    deadline: Instant,
+) -> Result<(), TlsError> {
+    let mut authorizations = Vec::new();
+    for url in &order.authorizations {
+        let authorization: AcmeAuthorization = client.get(url).await?;
+        if authorization.status == STATUS_PENDING {
+            authorizations.push((url.clone(), authorization));
+        }
+    }
+
+    let http_server = match authorizations.iter().any(|(_, authorization)| {
+        challenge_type(configuration, &authorization.identifier) == HTTP_01
+    }) {
+        true => Some(HttpChallengeServer::start(&configuration.http_listen)?),
+        false => None,
+    };
+
+    let mut dns_challenges = Vec::new();
+    let result = respond_challenges(
+        configuration,
+        instance,
+        client,
+        &authorizations,
+        http_server.as_ref(),
+        &mut dns_challenges,
+        deadline,
+    )
+    .await;
+
+    if let Some(http_server) = http_server {
+        http_server.stop().await;
+    }
+
+    if let Some(dns_hook) = &configuration.dns_hook {
+        for challenge in dns_challenges {
+            let cleanup = run_dns_hook(
+                dns_hook,
+                instance,
+                "cleanup",
+                &challenge.domain,
+                &challenge.key_authorization,
+            );
+            if let Err(error) = cleanup {
+                error!(
+                    "Unable to clean up ACME challenge of {}: {}",
+                    challenge.domain, error
+                );
+            }
+        }
+    }
+
+    result
+}
+
+fn certificate_request(names: &[String]) -> Result<(Vec<u8>, Secret), TlsError> {
+    let mut params = CertificateParams::new(Vec::<String>::new());
+    let mut distinguished_name = DistinguishedName::new();
+    distinguished_name.push(DnType::CommonName, names[0].as_str());
+    params.distinguished_name = distinguished_name;
+    params.subject_alt_names = names
+        .iter()
+        .map(|name| tls::subject_alt_name(name))
+        .collect();
+
+    let request = Certificate::from_params(params)?;
+    Ok((
+        request.serialize_request_der()?,
+        Secret::new(request.serialize_private_key_pem()),
+    ))
+}
+
+// The certificate comes first, followed by the chain of intermediate CAs
+fn split_chain(pem: &str) -> (String, Option<String>) {
+    let end = pem
+        .find(PEM_CERTIFICATE_END)
+        .map(|index| index + PEM_CERTIFICATE_END.len())
+        .unwrap_or(pem.len());
+    let (certificate, chain) = pem.split_at(end);
+
+    let chain = chain.trim();
+    let chain = match chain.is_empty() {
+        true => None,
+        false => Some(format!("{}\n", chain)),
+    };
+
+    (format!("{}\n", certificate.trim()), chain)
+}
+
+// Orders a certificate from an ACME CA, unless the one obtained on a previous run
+// is still current. Returns whether a certificate was issued.
+pub async fn provision_acme_certificate(
+    configuration: &AcmeConfiguration,
+    instance: &CloudInstance,
+) -> Result<bool, TlsError> {
+    let identifiers = identifiers(configuration, instance);
+    let names = identifiers
+        .iter()
+        .map(|identifier| identifier.value.clone())
+        .collect::<Vec<_>>();
+    if names.is_empty() {
+        return Err(acme_error("no name to request a certificate for"));
+    }
+
+    let certificate_path = configuration
+        .files
+        .certificate_path(&configuration.directory);
+    if tls::certificate_is_current(&certificate_path, &names) {
+        return Ok(false);
+    }
+
+    info!(
+        "Requesting certificate for {} from {}",
+        names.join(", "),
+        configuration.directory_url
+    );
+    let key = AccountKey::load_or_generate(
+        &Path::new(&configuration.directory).join(&configuration.account_key_file),
+    )?;
+    let mut client = AcmeClient::new(configuration, key).await?;
+    client
+        .register(
+            &configuration.contact,
+            configuration.terms_of_service_agreed,
+        )
+        .await?;
+
+    let deadline = Instant::now() + Duration::from_secs(configuration.validation_timeout_secs);
+    let (order_url, order) = client.new_order(&identifiers).await?;
+    authorize(configuration, instance, &mut client, &order, deadline).await?;
+    client
+        .wait_order(&order_url, STATUS_READY, deadline)
+        .await?;
+
+    let (request, private_key) = certificate_request(&names)?;
+    client
+        .post(
+            &order.finalize,
+            Some(&json!({ "csr": base64url(&request) })),
+        )
+        .await?;
+    let order = client
+        .wait_order(&order_url, STATUS_VALID, deadline)
+        .await?;
+
+    let certificate_url = order
+        .certificate
+        .ok_or_else(|| acme_error("no certificate in the valid order"))?;
+    let pem = client.post(&certificate_url, None).await?.body;
+    let (certificate, chain) = split_chain(&pem);
+
+    configuration.files.write(
+        &configuration.directory,
+        &IssuedCertificate {
+            certificate,
+            private_key,
+        },
+        chain.as_deref(),
+    )?;
+    info!("Certificate written to {}", configuration.directory);
+
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hyper::body::to_bytes;
+    use hyper::service::{make_service_fn, service_fn};
+    use hyper::{Body, Client, Request, Response, Server, StatusCode};
+    use std::convert::Infallible;
+    use std::fs;
+    use std::net::{SocketAddr, TcpListener};
+    use std::sync::{Arc, Mutex};
+
+    const NAME: &str = "web.example.test";
+    const TOKEN: &str = "mock-challenge-token";
+
+    const LEAF: &str = "-----BEGIN CERTIFICATE-----\nbGVhZg==\n-----END CERTIFICATE-----";
+    const INTERMEDIATE: &str =
+        "-----BEGIN CERTIFICATE-----\naW50ZXJtZWRpYXRl\n-----END CERTIFICATE-----";
+    const ROOT: &str = "-----BEGIN CERTIFICATE-----\ncm9vdA==\n-----END CERTIFICATE-----";
+
+    #[test]
+    fn split_chain_separates_certificate_from_intermediates() {
+        let pem = format!("{}\n{}\n{}\n", LEAF, INTERMEDIATE, ROOT);
+        let (certificate, chain) = split_chain(&pem);
+        assert_eq!(certificate, format!("{}\n", LEAF));
+        assert_eq!(chain, Some(format!("{}\n{}\n", INTERMEDIATE, ROOT)));
+    }
+
+    #[test]
+    fn split_chain_without_intermediates() {
+        let (certificate, chain) = split_chain(&format!("\n{}\n\n", LEAF));
+        assert_eq!(certificate, format!("{}\n", LEAF));
+        assert_eq!(chain, None);
+    }
+
+    // State of the mock CA, and the requests it received as "METHOD /path",
+    // nonce and decoded JWS payload
+    #[derive(Default)]
+    struct MockCa {
+        address: Option<SocketAddr>,
+        challenge_address: Option<SocketAddr>,
+        nonces: usize,
+        rejected_nonce: bool,
+        key_authorization: Option<String>,
+        csr: Option<String>,
+        order_polls: usize,
+        received: Vec<(String, Option<String>, Value)>,
+    }
+
+    type SharedCa = Arc<Mutex<MockCa>>;
+
+    fn decode(part: &str) -> Value {
+        match part.is_empty() {
+            true => Value::Null,
+            false => serde_json::from_slice(
+                &base64::decode_config(part, base64::URL_SAFE_NO_PAD).unwrap(),
+            )
+            .unwrap(),
+        }
+    }
+
+    async fn validate_challenge(address: SocketAddr) -> String {
+        let url = format!("http://{}/.well-known/acme-challenge/{}", address, TOKEN);
+        let response = Client::new().get(url.parse().unwrap()).await.unwrap();
+        String::from_utf8(to_bytes(response.into_body()).await.unwrap().to_vec()).unwrap()
+    }
+
+    async fn respond(ca: SharedCa, request: Request<Body>) -> Response<Body> {
+        let route = format!("{} {}", request.method(), request.uri().path());
+        let body = to_bytes(request.into_body()).await.unwrap();
+        let (nonce, payload) = match body.is_empty() {
+            true => (None, Value::Null),
+            false => {
+                let jws: Value = serde_json::from_slice(&body).unwrap();
+                let protected = decode(jws["protected"].as_str().unwrap());
+                (
+                    protected["nonce"].as_str().map(String::from),
+                    decode(jws["payload"].as_str().unwrap()),
+                )
+            }
+        };
+
+        if route == "POST /challenge/1" {
+            let challenge_address = ca.lock().unwrap().challenge_address.unwrap();
+            let key_authorization = validate_challenge(challenge_address).await;
+            ca.lock().unwrap().key_authorization = Some(key_authorization);
+        }
+
+        let mut ca = ca.lock().unwrap();
+        ca.received.push((route.clone(), nonce, payload.clone()));
+        let base = format!("http://{}", ca.address.unwrap());
+        let validated = ca.key_authorization.is_some();
+
+        let (status, location, body) = match route.as_str() {
+            "GET /directory" => (
+                StatusCode::OK,
+                None,
+                json!({
+                    "newNonce": format!("{}/new-nonce", base),
+                    "newAccount": format!("{}/new-account", base),
+                    "newOrder": format!("{}/new-order", base),
+                })
+                .to_string(),
+            ),
+            "HEAD /new-nonce" => (StatusCode::OK, None, String::new()),
+            // The first nonce is rejected, as if it had expired
+            "POST /new-account" if !ca.rejected_nonce => {
+                ca.rejected_nonce = true;
+                (
+                    StatusCode::BAD_REQUEST,
+                    None,
+                    json!({ "type": BAD_NONCE_ERROR }).to_string(),
+                )
+            }
+            "POST /new-account" => (
+                StatusCode::CREATED,
+                Some(format!("{}/account/1", base)),
+                json!({ "status": "valid" }).to_string(),
+            ),
+            "POST /new-order" => (
+                StatusCode::CREATED,
+                Some(format!("{}/order/1", base)),
+                json!({
+                    "status": "pending",
+                    "authorizations": [format!("{}/authz/1", base)],
+                    "finalize": format!("{}/finalize/1", base),
+                })
+                .to_string(),
+            ),
+            "POST /authz/1" => (
+                StatusCode::OK,
+                None,
+                json!({
+                    "identifier": { "type": "dns", "value": NAME },
+                    "status": if validated { "valid" } else { "pending" },
+                    "challenges": [
+                        { "type": "dns-01", "url": format!("{}/challenge/2", base), "token": "other" },
+                        { "type": "http-01", "url": format!("{}/challenge/1", base), "token": TOKEN },
+                    ],
+                })
+                .to_string(),
+            ),
+            "POST /challenge/1" => (StatusCode::OK, None, json!({}).to_string()),
+            "POST /finalize/1" => {
+                ca.csr = payload["csr"].as_str().map(String::from);
+                (StatusCode::OK, None, json!({}).to_string())
+            }
+            // Still processing on the first poll once finalized
+            "POST /order/1" => {
+                let status = match (&ca.csr, ca.order_polls) {
+                    (None, _) if validated => "ready",
+                    (None, _) => "pending",
+                    (Some(_), 0) => "processing",
+                    (Some(_), _) => "valid",
+                };
+                if ca.csr.is_some() {
+                    ca.order_polls += 1;
+                }
+                (
+                    StatusCode::OK,
+                    None,
+                    json!({
+                        "status": status,
+                        "authorizations": [format!("{}/authz/1", base)],
+                        "finalize": format!("{}/finalize/1", base),
+                        "certificate": (status == "valid").then(|| format!("{}/cert/1", base)),
+                    })
+                    .to_string(),
+                )
+            }
+            "POST /cert/1" => {
+                let issued = tls::issue_certificate(NAME, &[NAME.to_string()], 30, None).unwrap();
+                (
+                    StatusCode::OK,
+                    None,
+                    format!("{}{}\n", issued.certificate, INTERMEDIATE),
+                )
+            }
+            _ => (StatusCode::NOT_FOUND, None, String::new()),
+        };
+
+        ca.nonces += 1;
+        let mut response = Response::builder()
+            .status(status)
+            .header(REPLAY_NONCE_HEADER, format!("nonce-{}", ca.nonces));
+        if let Some(location) = location {
+            response = response.header(LOCATION_HEADER, location);
+        }
+        response.body(Body::from(body)).unwrap()
+    }
+
+    fn start_ca(challenge_address: SocketAddr) -> (SocketAddr, SharedCa) {
+        let ca = SharedCa::default();
+        let shared = ca.clone();
+        let make_service = make_service_fn(move |_| {
+            let ca = shared.clone();
+            async move {
+                Ok::<_, Infallible>(service_fn(move |request| {
+                    let ca = ca.clone();
+                    async move { Ok::<_, Infallible>(respond(ca, request).await) }
+                }))
+            }
+        });
+
+        let server = Server::bind(&"127.0.0.1:0".parse().unwrap()).serve(make_service);
+        let address = server.local_addr();
+        tokio::spawn(server);
+
+        let mut state = ca.lock().unwrap();
+        state.address = Some(address);
+        state.challenge_address = Some(challenge_address);
+        drop(state);
+        (address, ca)
+    }
+
+    #[tokio::test]
+    async fn orders_certificate_with_http_challenge() {
+        // A free port for the challenge server, only bound while validating
+        let challenge_address = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let (address, ca) = start_ca(challenge_address);
+        let directory =
+            std::env::temp_dir().join(format!("instance-init-{}-acme", std::process::id()));
+        let configuration: AcmeConfiguration = toml::from_str(&format!(
+            r#"
+            directory_url = "http://{}/directory"
+            contact = ["admin@example.test"]
+            terms_of_service_agreed = true
+            names = ["{}"]
+            http_listen = "{}"
+            directory = "{}"
+            "#,
+            address,
+            NAME,
+            challenge_address,
+            directory.display()
+        ))
+        .unwrap();
+
+        assert!(
+            provision_acme_certificate(&configuration, &CloudInstance::default())
+                .await
+                .unwrap()
+        );
+
+        {
+            let ca_state = ca.lock().unwrap();
+            let routes = ca_state
+                .received
+                .iter()
+                .map(|(route, _, _)| route.as_str())
+                .collect::<Vec<_>>();
+            assert_eq!(
+                routes,
+                [
+                    "GET /directory",
+                    "HEAD /new-nonce",
+                    "POST /new-account",
+                    // A new nonce is fetched once the first one is rejected
+                    "HEAD /new-nonce",
+                    "POST /new-account",
+                    "POST /new-order",
+                    "POST /authz/1",
+                    "POST /challenge/1",
+                    "POST /authz/1",
+                    "POST /order/1",
+                    "POST /finalize/1",
+                    "POST /order/1",
+                    "POST /order/1",
+                    "POST /cert/1",
+                ]
+            );
+            let received = &ca_state.received;
+            assert_eq!(received[2].1.as_deref(), Some("nonce-2"));
+            assert_eq!(received[4].1.as_deref(), Some("nonce-4"));
+            assert_eq!(
+                received[4].2,
+                json!({ "termsOfServiceAgreed": true, "contact": ["mailto:admin@example.test"] })
+            );
+            assert_eq!(
+                received[5].2,
+                json!({ "identifiers": [{ "type": "dns", "value": NAME }] })
+            );
+            assert_eq!(received[6].2, Value::Null);
+
+            let key = AccountKey::load_or_generate(&directory.join("account.key")).unwrap();
+            assert_eq!(
+                ca_state.key_authorization,
+                Some(format!("{}.{}", TOKEN, key.thumbprint()))
+            );
+            assert!(ca_state.csr.is_some());
+        }
+
+        assert!(fs::read_to_string(directory.join("chain.pem"))
+            .unwrap()
+            .contains("aW50ZXJtZWRpYXRl"));
+        assert!(tls::certificate_is_current(
+            &directory.join("cert.pem"),
+            &[NAME.to_string()]
+        ));
+
+        // Still current, nothing is requested
+        assert!(
+            !provision_acme_certificate(&configuration, &CloudInstance::default())
+                .await
+                .unwrap()
+        );
+        assert_eq!(ca.lock().unwrap().received.len(), 14);
+
+        fs::remove_dir_all(&directory).unwrap();
+    }
+}
@@ -0,0 +1,57 @@
+use crate::host::HostError;
+use crate::http_client::HttpError;
+use std::fmt;
+use std::io;
+
+#[derive(Debug)]
+pub enum TlsError {
+    InvalidAuthority(String),
+    GenerationError(String),
+    AuthenticationError(String),
+    AcmeError(String),
+    RequestError(HttpError),
+    HostError(HostError),
+    IOError(io::Error),
+}
+
+impl From<io::Error> for TlsError {
+    fn from(error: io::Error) -> Self {
+        TlsError::IOError(error)
+    }
+}
+
+impl From<HostError> for TlsError {
+    fn from(error: HostError) -> Self {
+        TlsError::HostError(error)
+    }
+}
+
+impl From<HttpError> for TlsError {
+    fn from(error: HttpError) -> Self {
+        TlsError::RequestError(error)
+    }
+}
+
+impl From<rcgen::Error> for TlsError {
+    fn from(error: rcgen::Error) -> Self {
+        TlsError::GenerationError(error.to_string())
+    }
+}
+
+impl fmt::Display for TlsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TlsError::InvalidAuthority(reason) => {
+                write!(f, "invalid certificate authority: {}", reason)
+            }
+            TlsError::GenerationError(reason) => {
+                write!(f, "unable to generate certificate: {}", reason)
+            }
+            TlsError::AuthenticationError(reason) => write!(f, "authentication failed: {}", reason),
+            TlsError::AcmeError(reason) => write!(f, "ACME error: {}", reason),
+            TlsError::RequestError(error) => write!(f, "{}", error),
+            TlsError::HostError(error) => write!(f, "{}", error),
+            TlsError::IOError(error) => write!(f, "I/O error: {}", error),
+        }
+    }
+}
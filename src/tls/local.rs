@@ -0,0 +1,242 @@
+use crate::provider::CloudInstance;
+use crate::secret::Secret;
+use crate::tls::{self, CertificateAuthority, CertificateFiles, TlsError};
+use log::info;
+use serde::Deserialize;
+
+#[derive(Clone, Deserialize, Debug)]
+pub struct LocalCertificateConfiguration {
+    // PEM encoded CA signing the certificate, which is self-signed without one
+    pub ca_certificate: Option<String>,
+    pub ca_key: Option<Secret>,
+    // Defaults to the instance hostname
+    pub common_name: Option<String>,
+    // Added to the instance hostname and addresses, DNS names and IP addresses alike
+    #[serde(default)]
+    pub alt_names: Vec<String>,
+    #[serde(default = "default_validity_days")]
+    pub validity_days: u32,
+    #[serde(default = "default_directory")]
+    pub directory: String,
+    #[serde(flatten)]
+    pub files: CertificateFiles,
+}
+
+fn default_validity_days() -> u32 {
+    365
+}
+
+fn default_directory() -> String {
+    "/var/lib/instance-init/tls/local".to_string()
+}
+
+// Generates a key pair and a certificate for the instance, unless the one from a
+// previous run is still current and issued by the same CA. Returns whether a
+// certificate was issued.
+pub fn provision_local_certificate(
+    configuration: &LocalCertificateConfiguration,
+    instance: &CloudInstance,
+) -> Result<bool, TlsError> {
+    let authority = match (&configuration.ca_certificate, &configuration.ca_key) {
+        (Some(certificate), Some(key)) => Some(CertificateAuthority::from_pem(certificate, key)?),
+        (None, None) => None,
+        _ => {
+            return Err(TlsError::InvalidAuthority(
+                "both ca_certificate and ca_key are required".to_string(),
+            ))
+        }
+    };
+
+    let common_name = configuration
+        .common_name
+        .clone()
+        .unwrap_or_else(|| instance.hostname.clone());
+    let names = tls::instance_names(instance, &configuration.alt_names);
+
+    let certificate_path = configuration
+        .files
+        .certificate_path(&configuration.directory);
+    let authority_pem = authority
+        .as_ref()
+        .map(|authority| authority.certificate_pem());
+    if tls::certificate_issued_by(&certificate_path, authority_pem)
+        && tls::certificate_is_current(&certificate_path, &names)
+    {
+        return Ok(false);
+    }
+
+    match &authority {
+        Some(_) => info!(
+            "Issuing certificate for {} with the configured CA",
+            common_name
+        ),
+        None => info!("Issuing self-signed certificate for {}", common_name),
+    }
+
+    let certificate = tls::issue_certificate(
+        &common_name,
+        &names,
+        configuration.validity_days,
+        authority.as_ref(),
+    )?;
+
+    // A self-signed certificate is its own chain
+    let chain = authority_pem
+        .unwrap_or(&certificate.certificate)
+        .to_string();
+    configuration
+        .files
+        .write(&configuration.directory, &certificate, Some(&chain))?;
+    info!("Certificate written to {}", configuration.directory);
+
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rcgen::{BasicConstraints, Certificate, CertificateParams, DnType, IsCa, KeyUsagePurpose};
+    use std::fs;
+    use std::path::{Path, PathBuf};
+
+    // A CA named "Internal CA", a new one with the same name standing for a renewed CA
+    fn authority() -> (String, Secret) {
+        let mut params = CertificateParams::new(Vec::<String>::new());
+        params
+            .distinguished_name
+            .push(DnType::CommonName, "Internal CA");
+        params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        params.key_usages = vec![KeyUsagePurpose::KeyCertSign, KeyUsagePurpose::CrlSign];
+
+        let certificate = Certificate::from_params(params).unwrap();
+        (
+            certificate.serialize_pem().unwrap(),
+            Secret::new(certificate.serialize_private_key_pem()),
+        )
+    }
+
+    fn configuration(
+        directory: &Path,
+        authority: Option<&(String, Secret)>,
+    ) -> LocalCertificateConfiguration {
+        let mut configuration: LocalCertificateConfiguration = toml::from_str(&format!(
+            r#"
+            alt_names = ["db.internal"]
+            directory = "{}"
+            "#,
+            directory.display()
+        ))
+        .unwrap();
+        configuration.ca_certificate = authority.map(|(certificate, _)| certificate.clone());
+        configuration.ca_key = authority.map(|(_, key)| key.clone());
+        configuration
+    }
+
+    fn directory(name: &str) -> PathBuf {
+        let directory = std::env::temp_dir().join(format!(
+            "instance-init-{}-local-{}",
+            std::process::id(),
+            name
+        ));
+        let _ = fs::remove_dir_all(&directory);
+        directory
+    }
+
+    fn instance() -> CloudInstance {
+        CloudInstance {
+            hostname: "db-1".to_string(),
+            ipv4_address: Some("192.0.2.20".to_string()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn self_signed_certificate_is_kept_while_current() {
+        let directory = directory("self-signed");
+        let configuration = configuration(&directory, None);
+        let path = directory.join("cert.pem");
+
+        assert!(provision_local_certificate(&configuration, &instance()).unwrap());
+        assert!(tls::certificate_issued_by(&path, None));
+        assert!(tls::certificate_is_current(
+            &path,
+            &[
+                "db-1".to_string(),
+                "192.0.2.20".to_string(),
+                "db.internal".to_string()
+            ]
+        ));
+        assert_eq!(
+            fs::read_to_string(directory.join("chain.pem")).unwrap(),
+            fs::read_to_string(&path).unwrap()
+        );
+
+        assert!(!provision_local_certificate(&configuration, &instance()).unwrap());
+
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn certificate_issued_by_the_ca_is_kept_while_current() {
+        let directory = directory("ca");
+        let authority = authority();
+        let configuration = configuration(&directory, Some(&authority));
+        let path = directory.join("cert.pem");
+
+        assert!(provision_local_certificate(&configuration, &instance()).unwrap());
+        assert!(tls::certificate_issued_by(&path, Some(&authority.0)));
+        assert!(!tls::certificate_issued_by(&path, None));
+        assert_eq!(
+            fs::read_to_string(directory.join("chain.pem")).unwrap(),
+            authority.0
+        );
+
+        assert!(!provision_local_certificate(&configuration, &instance()).unwrap());
+
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn rotated_ca_issues_a_new_certificate() {
+        let directory = directory("rotated");
+        let path = directory.join("cert.pem");
+
+        assert!(
+            provision_local_certificate(&configuration(&directory, None), &instance()).unwrap()
+        );
+
+        // From self-signed to the CA, then to a renewed CA with the same name
+        let previous = authority();
+        assert!(!tls::certificate_issued_by(&path, Some(&previous.0)));
+        assert!(provision_local_certificate(
+            &configuration(&directory, Some(&previous)),
+            &instance()
+        )
+        .unwrap());
+
+        let renewed = authority();
+        assert!(!tls::certificate_issued_by(&path, Some(&renewed.0)));
+        assert!(provision_local_certificate(
+            &configuration(&directory, Some(&renewed)),
+            &instance()
+        )
+        .unwrap());
+        assert!(tls::certificate_issued_by(&path, Some(&renewed.0)));
+        assert!(!tls::certificate_issued_by(&path, Some(&previous.0)));
+
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn rejects_incomplete_ca() {
+        let directory = directory("incomplete");
+        let mut configuration = configuration(&directory, Some(&authority()));
+        configuration.ca_key = None;
+
+        assert!(matches!(
+            provision_local_certificate(&configuration, &instance()),
+            Err(TlsError::InvalidAuthority(_))
+        ));
+        assert!(!directory.exists());
+    }
+}
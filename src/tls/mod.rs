@@ -0,0 +1,411 @@
+mod acme;
+mod error;
+mod local;
+mod vault;
+
+pub use acme::{provision_acme_certificate, AcmeConfiguration};
+pub use error::TlsError;
+pub use local::{provision_local_certificate, LocalCertificateConfiguration};
+pub use vault::{provision_vault_certificate, VaultConfiguration};
+
+use crate::host;
+use crate::provider::CloudInstance;
+use crate::secret::Secret;
+use log::debug;
+use rcgen::{
+    Certificate, CertificateParams, DistinguishedName, DnType, ExtendedKeyUsagePurpose, KeyPair,
+    KeyUsagePurpose, SanType,
+};
+use serde::Deserialize;
+use std::fs;
+use std::net::IpAddr;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use time::{Duration, OffsetDateTime};
+use x509_parser::certificate::X509Certificate;
+use x509_parser::extensions::{GeneralName, ParsedExtension};
+use x509_parser::pem::{parse_x509_pem, Pem};
+
+const PUBLIC_FILE_MODE: u32 = 0o644;
+const PRIVATE_FILE_MODE: u32 = 0o600;
+
+// Certificates are backdated a little to tolerate clock skew between hosts
+const NOT_BEFORE_MARGIN_MINUTES: i64 = 5;
+
+// Where a certificate is written, relative names being taken in the directory of the module
+#[derive(Clone, Deserialize, Debug)]
+pub struct CertificateFiles {
+    #[serde(default = "default_certificate_file")]
+    pub certificate_file: String,
+    #[serde(default = "default_key_file")]
+    pub key_file: String,
+    #[serde(default = "default_chain_file")]
+    pub chain_file: String,
+    // Owner of the files, e.g. "nginx" or "root:ssl-cert"
+    pub owner: Option<String>,
+}
+
+fn default_certificate_file() -> String {
+    "cert.pem".to_string()
+}
+
+fn default_key_file() -> String {
+    "key.pem".to_string()
+}
+
+fn default_chain_file() -> String {
+    "chain.pem".to_string()
+}
+
+impl CertificateFiles {
+    pub fn certificate_path(&self, directory: &str) -> PathBuf {
+        Path::new(directory).join(&self.certificate_file)
+    }
+
+    pub fn write(
+        &self,
+        directory: &str,
+        certificate: &IssuedCertificate,
+        chain: Option<&str>,
+    ) -> Result<(), TlsError> {
+        let owner = self.owner.as_deref();
+        let directory = Path::new(directory);
+
+        // The key goes first, so that a certificate is never left without its key
+        write_pem(
+            &directory.join(&self.key_file),
+            certificate.private_key.expose(),
+            true,
+            owner,
+        )?;
+        write_pem(
+            &directory.join(&self.certificate_file),
+            &certificate.certificate,
+            false,
+            owner,
+        )?;
+        if let Some(chain) = chain {
+            write_pem(&directory.join(&self.chain_file), chain, false, owner)?;
+        }
+
+        Ok(())
+    }
+}
+
+fn covers_name(names: &[GeneralName], name: &str) -> bool {
+    match name.parse::<IpAddr>() {
+        Ok(IpAddr::V4(address)) => names
+            .iter()
+            .any(|san| matches!(san, GeneralName::IPAddress(bytes) if *bytes == address.octets())),
+        Ok(IpAddr::V6(address)) => names
+            .iter()
+            .any(|san| matches!(san, GeneralName::IPAddress(bytes) if *bytes == address.octets())),
+        Err(_) => names
+            .iter()
+            .any(|san| matches!(san, GeneralName::DNSName(dns) if dns.eq_ignore_ascii_case(name))),
+    }
+}
+
+fn read_pem(path: &Path) -> Option<Pem> {
+    let content = fs::read(path).ok()?;
+    match parse_x509_pem(&content) {
+        Ok((_, pem)) => Some(pem),
+        Err(err) => {
+            debug!("Unable to read certificate {}: {}", path.display(), err);
+            None
+        }
+    }
+}
+
+// An existing certificate is kept until two thirds of its lifetime, as long as it
+// still covers every name (e.g. after the instance got a new address)
+pub fn certificate_is_current(path: &Path, names: &[String]) -> bool {
+    let pem = match read_pem(path) {
+        Some(pem) => pem,
+        None => return false,
+    };
+
+    let certificate = match pem.parse_x509() {
+        Ok(certificate) => certificate,
+        Err(err) => {
+            debug!("Unable to parse certificate {}: {}", path.display(), err);
+            return false;
+        }
+    };
+
+    let not_before = certificate.validity().not_before.timestamp();
+    let not_after = certificate.validity().not_after.timestamp();
+    let renewal = not_after - (not_after - not_before) / 3;
+    if OffsetDateTime::now_utc().unix_timestamp() >= renewal {
+        debug!("Certificate {} is due for renewal", path.display());
+        return false;
+    }
+
+    let sans = match certificate.subject_alternative_name() {
+        Ok(Some(extension)) => extension.value.general_names.clone(),
+        _ => Vec::new(),
+    };
+
+    names.iter().all(|name| covers_name(&sans, name))
+}
+
+fn authority_key_identifier<'a>(certificate: &'a X509Certificate) -> Option<&'a [u8]> {
+    certificate
+        .extensions()
+        .iter()
+        .find_map(|extension| match extension.parsed_extension() {
+            ParsedExtension::AuthorityKeyIdentifier(identifier) => identifier
+                .key_identifier
+                .as_ref()
+                .map(|identifier| identifier.0),
+            _ => None,
+        })
+}
+
+fn subject_key_identifier<'a>(certificate: &'a X509Certificate) -> Option<&'a [u8]> {
+    certificate
+        .extensions()
+        .iter()
+        .find_map(|extension| match extension.parsed_extension() {
+            ParsedExtension::SubjectKeyIdentifier(identifier) => Some(identifier.0),
+            _ => None,
+        })
+}
+
+// Whether an existing certificate was issued by the CA, or is self-signed without one
+pub fn certificate_issued_by(path: &Path, authority: Option<&str>) -> bool {
+    let pem = match read_pem(path) {
+        Some(pem) => pem,
+        None => return false,
+    };
+    let certificate = match pem.parse_x509() {
+        Ok(certificate) => certificate,
+        Err(_) => return false,
+    };
+
+    let authority_pem = match authority.map(|authority| parse_x509_pem(authority.as_bytes())) {
+        Some(Ok((_, pem))) => pem,
+        Some(Err(_)) => return false,
+        None => return certificate.issuer().as_raw() == certificate.subject().as_raw(),
+    };
+    let authority = match authority_pem.parse_x509() {
+        Ok(authority) => authority,
+        Err(_) => return false,
+    };
+
+    if certificate.issuer().as_raw() != authority.subject().as_raw() {
+        return false;
+    }
+
+    // A CA renewed with the same name is told apart by its key identifier
+    match (
+        authority_key_identifier(&certificate),
+        subject_key_identifier(&authority),
+    ) {
+        (Some(issuer), Some(authority)) => issuer == authority,
+        _ => true,
+    }
+}
+
+// A CA loaded from configuration, used to sign the generated certificates
+pub struct CertificateAuthority {
+    certificate_pem: String,
+    certificate: Certificate,
+}
+
+impl CertificateAuthority {
+    pub fn from_pem(certificate: &str, private_key: &Secret) -> Result<Self, TlsError> {
+        let key_pair = KeyPair::from_pem(private_key.expose())
+            .map_err(|err| TlsError::InvalidAuthority(format!("private key: {}", err)))?;
+        let params = CertificateParams::from_ca_cert_pem(certificate, key_pair)
+            .map_err(|err| TlsError::InvalidAuthority(format!("certificate: {}", err)))?;
+
+        Ok(Self {
+            certificate_pem: certificate.to_string(),
+            certificate: Certificate::from_params(params)?,
+        })
+    }
+
+    pub fn certificate_pem(&self) -> &str {
+        &self.certificate_pem
+    }
+}
+
+pub struct IssuedCertificate {
+    pub certificate: String,
+    pub private_key: Secret,
+}
+
+// The instance hostname and addresses, followed by the extra names
+pub fn instance_names(instance: &CloudInstance, extra: &[String]) -> Vec<String> {
+    let mut names = vec![instance.hostname.clone()];
+    names.extend(instance.ipv4_address.iter().cloned());
+    names.extend(instance.ipv6_address.iter().cloned());
+    for name in extra {
+        if !names.contains(name) {
+            names.push(name.clone());
+        }
+    }
+    names.retain(|name| !name.is_empty());
+    names
+}
+
+fn subject_alt_name(name: &str) -> SanType {
+    match name.parse::<IpAddr>() {
+        Ok(address) => SanType::IpAddress(address),
+        Err(_) => SanType::DnsName(name.to_string()),
+    }
+}
+
+// Issues a certificate usable both as server and client (e.g. between peers),
+// signed by the authority or self-signed without one
+pub fn issue_certificate(
+    common_name: &str,
+    names: &[String],
+    validity_days: u32,
+    authority: Option<&CertificateAuthority>,
+) -> Result<IssuedCertificate, TlsError> {
+    let mut params = CertificateParams::new(Vec::<String>::new());
+    let mut distinguished_name = DistinguishedName::new();
+    distinguished_name.push(DnType::CommonName, common_name);
+    params.distinguished_name = distinguished_name;
+    params.subject_alt_names = names.iter().map(|name| subject_alt_name(name)).collect();
+    params.key_usages = vec![
+        KeyUsagePurpose::DigitalSignature,
+        KeyUsagePurpose::KeyEncipherment,
+    ];
+    params.extended_key_usages = vec![
+        ExtendedKeyUsagePurpose::ServerAuth,
+        ExtendedKeyUsagePurpose::ClientAuth,
+    ];
+
+    let now = OffsetDateTime::now_utc();
+    params.not_before = now - Duration::minutes(NOT_BEFORE_MARGIN_MINUTES);
+    params.not_after = now + Duration::days(validity_days.into());
+    params.use_authority_key_identifier_extension = authority.is_some();
+
+    let certificate = Certificate::from_params(params)?;
+    let certificate_pem = match authority {
+        Some(authority) => certificate.serialize_pem_with_signer(&authority.certificate)?,
+        None => certificate.serialize_pem()?,
+    };
+
+    Ok(IssuedCertificate {
+        certificate: certificate_pem,
+        private_key: Secret::new(certificate.serialize_private_key_pem()),
+    })
+}
+
+// Writes a PEM file readable by its owner only when it holds a private key
+pub fn write_pem(
+    path: &Path,
+    content: &str,
+    private: bool,
+    owner: Option<&str>,
+) -> Result<(), TlsError> {
+    if let Some(directory) = path.parent() {
+        fs::create_dir_all(directory)?;
+    }
+
+    let mode = match private {
+        true => PRIVATE_FILE_MODE,
+        false => PUBLIC_FILE_MODE,
+    };
+
+    // Created with restricted permissions before the key is written
+    fs::write(path, "")?;
+    fs::set_permissions(path, fs::Permissions::from_mode(mode))?;
+    fs::write(path, content)?;
+
+    if let Some(owner) = owner {
+        host::set_owner(path, owner)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write_certificate(name: &str, names: &[&str], lifetime: (Duration, Duration)) -> PathBuf {
+        let mut params = CertificateParams::new(Vec::<String>::new());
+        params.subject_alt_names = names.iter().map(|name| subject_alt_name(name)).collect();
+        let now = OffsetDateTime::now_utc();
+        params.not_before = now + lifetime.0;
+        params.not_after = now + lifetime.1;
+
+        let certificate = Certificate::from_params(params).unwrap();
+        let path =
+            std::env::temp_dir().join(format!("instance-init-{}-{}.pem", std::process::id(), name));
+        fs::write(&path, certificate.serialize_pem().unwrap()).unwrap();
+        path
+    }
+
+    fn names(names: &[&str]) -> Vec<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    #[test]
+    fn current_certificate_covering_every_name() {
+        let path = write_certificate(
+            "current",
+            &["web-1", "WWW.example.com", "192.0.2.10", "2001:db8::10"],
+            (Duration::days(-1), Duration::days(89)),
+        );
+        assert!(certificate_is_current(
+            &path,
+            &names(&["web-1", "www.example.com", "192.0.2.10"])
+        ));
+        assert!(certificate_is_current(&path, &names(&["2001:db8::10"])));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn renews_past_two_thirds_of_lifetime() {
+        let path = write_certificate(
+            "expiring",
+            &["web-1"],
+            (Duration::days(-61), Duration::days(29)),
+        );
+        assert!(!certificate_is_current(&path, &names(&["web-1"])));
+        fs::remove_file(&path).unwrap();
+
+        let path = write_certificate(
+            "expired",
+            &["web-1"],
+            (Duration::days(-90), Duration::days(-1)),
+        );
+        assert!(!certificate_is_current(&path, &names(&["web-1"])));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn renews_when_a_name_is_missing() {
+        let path = write_certificate(
+            "names",
+            &["web-1", "192.0.2.10"],
+            (Duration::days(-1), Duration::days(89)),
+        );
+        assert!(!certificate_is_current(
+            &path,
+            &names(&["web-1", "api.example.com"])
+        ));
+        assert!(!certificate_is_current(
+            &path,
+            &names(&["web-1", "192.0.2.11"])
+        ));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn missing_or_invalid_certificate_is_not_current() {
+        let path =
+            std::env::temp_dir().join(format!("instance-init-{}-missing.pem", std::process::id()));
+        assert!(!certificate_is_current(&path, &names(&["web-1"])));
+
+        fs::write(&path, "not a certificate").unwrap();
+        assert!(!certificate_is_current(&path, &names(&["web-1"])));
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,435 @@
+use crate::http_client::{HttpClient, HttpRequest, RetryPolicy, TlsConfiguration};
+use crate::provider::CloudInstance;
+use crate::secret::Secret;
+use crate::tls::{self, CertificateFiles, IssuedCertificate, TlsError};
+use hyper::Method;
+use log::{debug, info};
+use serde::{Deserialize, Serialize};
+use std::net::IpAddr;
+use std::time::Duration;
+
+const VAULT_TOKEN_HEADER: &str = "X-Vault-Token";
+const VAULT_NAMESPACE_HEADER: &str = "X-Vault-Namespace";
+
+const VAULT_RETRY_ATTEMPTS: u32 = 3;
+const VAULT_RETRY_INITIAL_DELAY_MILLIS: u64 = 500;
+const VAULT_RETRY_MAX_DELAY_SECS: u64 = 5;
+
+#[derive(Clone, Deserialize, Debug)]
+pub struct VaultConfiguration {
+    // e.g. https://vault.internal:8200
+    pub address: String,
+    pub namespace: Option<String>,
+    // Either a token, or an AppRole role and secret ids
+    pub token: Option<Secret>,
+    pub role_id: Option<String>,
+    pub secret_id: Option<Secret>,
+    #[serde(default = "default_approle_mount")]
+    pub approle_mount: String,
+    #[serde(default = "default_pki_mount")]
+    pub pki_mount: String,
+    // PKI role the certificate is issued for
+    pub role: String,
+    // Defaults to the instance hostname
+    pub common_name: Option<String>,
+    // Added to the instance hostname and addresses, DNS names and IP addresses alike
+    #[serde(default)]
+    pub alt_names: Vec<String>,
+    pub ttl: Option<String>,
+    #[serde(default = "default_directory")]
+    pub directory: String,
+    #[serde(flatten)]
+    pub files: CertificateFiles,
+    #[serde(default = "default_timeout_secs")]
+    pub timeout_secs: u64,
+    #[serde(default)]
+    pub tls: TlsConfiguration,
+}
+
+fn default_approle_mount() -> String {
+    "approle".to_string()
+}
+
+fn default_pki_mount() -> String {
+    "pki".to_string()
+}
+
+fn default_directory() -> String {
+    "/var/lib/instance-init/tls/vault".to_string()
+}
+
+fn default_timeout_secs() -> u64 {
+    10
+}
+
+#[derive(Serialize)]
+struct VaultLoginRequest<'a> {
+    role_id: &'a str,
+    secret_id: &'a str,
+}
+
+#[derive(Deserialize)]
+struct VaultAuth {
+    client_token: String,
+}
+
+#[derive(Deserialize)]
+struct VaultLoginResponse {
+    auth: VaultAuth,
+}
+
+#[derive(Serialize)]
+struct VaultIssueRequest<'a> {
+    common_name: &'a str,
+    alt_names: String,
+    ip_sans: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    ttl: Option<&'a str>,
+}
+
+#[derive(Deserialize)]
+struct VaultIssuedCertificate {
+    certificate: String,
+    private_key: String,
+    issuing_ca: Option<String>,
+    #[serde(default)]
+    ca_chain: Vec<String>,
+    serial_number: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct VaultIssueResponse {
+    data: VaultIssuedCertificate,
+}
+
+struct VaultClient<'a> {
+    configuration: &'a VaultConfiguration,
+    http_client: HttpClient,
+}
+
+impl<'a> VaultClient<'a> {
+    fn new(configuration: &'a VaultConfiguration) -> Result<Self, TlsError> {
+        let mut http_client = HttpClient::new(configuration.timeout_secs);
+        http_client.set_tls(&configuration.tls)?;
+        http_client.set_retry_policy(RetryPolicy::new(
+            VAULT_RETRY_ATTEMPTS,
+            Duration::from_millis(VAULT_RETRY_INITIAL_DELAY_MILLIS),
+            Duration::from_secs(VAULT_RETRY_MAX_DELAY_SECS),
+        ));
+
+        Ok(Self {
+            configuration,
+            http_client,
+        })
+    }
+
+    fn request(&self, method: Method, path: &str) -> HttpRequest {
+        let uri = format!(
+            "{}/v1/{}",
+            self.configuration.address.trim_end_matches('/'),
+            path
+        );
+        let request = HttpRequest::new(method, uri);
+
+        match &self.configuration.namespace {
+            Some(namespace) => request.header(VAULT_NAMESPACE_HEADER, namespace),
+            None => request,
+        }
+    }
+
+    // A configured token is used as is, otherwise one is obtained with AppRole
+    async fn token(&self) -> Result<Secret, TlsError> {
+        if let Some(token) = &self.configuration.token {
+            return Ok(token.clone());
+        }
+
+        let (role_id, secret_id) =
+            match (&self.configuration.role_id, &self.configuration.secret_id) {
+                (Some(role_id), Some(secret_id)) => (role_id, secret_id),
+                _ => {
+                    return Err(TlsError::AuthenticationError(
+                        "either a token or an AppRole role_id and secret_id are required"
+                            .to_string(),
+                    ))
+                }
+            };
+
+        debug!("Logging in to Vault with AppRole {}", role_id);
+        let path = format!(
+            "auth/{}/login",
+            self.configuration.approle_mount.trim_matches('/')
+        );
+        let request = self
+            .request(Method::POST, &path)
+            .sensitive()
+            .json(&VaultLoginRequest {
+                role_id,
+                secret_id: secret_id.expose(),
+            })?;
+        let response: VaultLoginResponse = self.http_client.send_json(request).await?;
+
+        Ok(Secret::new(response.auth.client_token))
+    }
+
+    async fn issue(
+        &self,
+        common_name: &str,
+        names: &[String],
+    ) -> Result<VaultIssuedCertificate, TlsError> {
+        let token = self.token().await?;
+
+        let (ip_sans, alt_names): (Vec<&String>, Vec<&String>) = names
+            .iter()
+            .partition(|name| name.parse::<IpAddr>().is_ok());
+        let join = |names: Vec<&String>| {
+            names
+                .iter()
+                .map(|name| name.as_str())
+                .collect::<Vec<_>>()
+                .join(",")
+        };
+
+        let path = format!(
+            "{}/issue/{}",
+            self.configuration.pki_mount.trim_matches('/'),
+            self.configuration.role
+        );
+        let request = self
+            .request(Method::POST, &path)
+            .sensitive()
+            .header(VAULT_TOKEN_HEADER, token.expose())
+            .json(&VaultIssueRequest {
+                common_name,
+                alt_names: join(alt_names),
+                ip_sans: join(ip_sans),
+                ttl: self.configuration.ttl.as_deref(),
+            })?;
+        let response: VaultIssueResponse = self.http_client.send_json(request).await?;
+
+        Ok(response.data)
+    }
+}
+
+// Requests a certificate from a Vault PKI role, unless the one issued on a previous
+// run is still current. Returns whether a certificate was issued.
+pub async fn provision_vault_certificate(
+    configuration: &VaultConfiguration,
+    instance: &CloudInstance,
+) -> Result<bool, TlsError> {
+    let common_name = configuration
+        .common_name
+        .clone()
+        .unwrap_or_else(|| instance.hostname.clone());
+    let names = tls::instance_names(instance, &configuration.alt_names);
+
+    let certificate_path = configuration
+        .files
+        .certificate_path(&configuration.directory);
+    if tls::certificate_is_current(&certificate_path, &names) {
+        return Ok(false);
+    }
+
+    info!(
+        "Requesting certificate for {} from Vault role {}",
+        common_name, configuration.role
+    );
+    let issued = VaultClient::new(configuration)?
+        .issue(&common_name, &names)
+        .await?;
+
+    // The chain of an intermediate CA lists every CA up to the root, the issuing
+    // one otherwise
+    let chain = match issued.ca_chain.is_empty() {
+        true => issued.issuing_ca.clone(),
+        false => Some(issued.ca_chain.join("\n")),
+    };
+
+    configuration.files.write(
+        &configuration.directory,
+        &IssuedCertificate {
+            certificate: issued.certificate,
+            private_key: Secret::new(issued.private_key),
+        },
+        chain.as_deref(),
+    )?;
+
+    if let Some(serial_number) = issued.serial_number {
+        info!(
+            "Certificate {} written to {}",
+            serial_number, configuration.directory
+        );
+    }
+
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::secret;
+    use hyper::body::to_bytes;
+    use hyper::service::{make_service_fn, service_fn};
+    use hyper::{Body, Request, Response, Server, StatusCode};
+    use serde_json::{json, Value};
+    use std::convert::Infallible;
+    use std::fs;
+    use std::net::SocketAddr;
+    use std::path::Path;
+    use std::sync::{Arc, Mutex};
+
+    const ROLE_ID: &str = "4e1c1e8a-role";
+    const SECRET_ID: &str = "0b5f2d7c-secret";
+    const CLIENT_TOKEN: &str = "hvs.mock-client-token";
+
+    // Requests received by the mock, as "METHOD /path" and JSON body
+    type Received = Arc<Mutex<Vec<(String, Value)>>>;
+
+    async fn respond(received: Received, request: Request<Body>) -> Response<Body> {
+        let route = format!("{} {}", request.method(), request.uri().path());
+        let token = request
+            .headers()
+            .get(VAULT_TOKEN_HEADER)
+            .map(|value| value.to_str().unwrap().to_string());
+        let body: Value =
+            serde_json::from_slice(&to_bytes(request.into_body()).await.unwrap()).unwrap();
+        received.lock().unwrap().push((route.clone(), body.clone()));
+
+        let response = match (route.as_str(), token.as_deref()) {
+            ("POST /v1/auth/approle/login", None) => {
+                json!({ "auth": { "client_token": CLIENT_TOKEN } })
+            }
+            ("POST /v1/pki/issue/web", Some(CLIENT_TOKEN)) => {
+                let names = [&body["alt_names"], &body["ip_sans"]]
+                    .iter()
+                    .flat_map(|names| names.as_str().unwrap().split(','))
+                    .filter(|name| !name.is_empty())
+                    .map(str::to_string)
+                    .collect::<Vec<_>>();
+                let issued =
+                    tls::issue_certificate(body["common_name"].as_str().unwrap(), &names, 30, None)
+                        .unwrap();
+                json!({ "data": {
+                    "certificate": issued.certificate,
+                    "private_key": issued.private_key.expose(),
+                    "issuing_ca": "-----BEGIN CERTIFICATE-----\nY2E=\n-----END CERTIFICATE-----",
+                    "serial_number": "01:02",
+                }})
+            }
+            _ => {
+                let mut response = Response::new(Body::from(r#"{"errors":["permission denied"]}"#));
+                *response.status_mut() = StatusCode::FORBIDDEN;
+                return response;
+            }
+        };
+
+        Response::new(Body::from(response.to_string()))
+    }
+
+    fn start_vault() -> (SocketAddr, Received) {
+        let received = Received::default();
+        let shared = received.clone();
+        let make_service = make_service_fn(move |_| {
+            let received = shared.clone();
+            async move {
+                Ok::<_, Infallible>(service_fn(move |request| {
+                    let received = received.clone();
+                    async move { Ok::<_, Infallible>(respond(received, request).await) }
+                }))
+            }
+        });
+
+        let server = Server::bind(&"127.0.0.1:0".parse().unwrap()).serve(make_service);
+        let address = server.local_addr();
+        tokio::spawn(server);
+        (address, received)
+    }
+
+    fn configuration(address: SocketAddr, directory: &Path) -> VaultConfiguration {
+        toml::from_str(&format!(
+            r#"
+            address = "http://{}"
+            role_id = "{}"
+            secret_id = "{}"
+            role = "web"
+            alt_names = ["api.internal"]
+            directory = "{}"
+            "#,
+            address,
+            ROLE_ID,
+            SECRET_ID,
+            directory.display()
+        ))
+        .unwrap()
+    }
+
+    fn instance() -> CloudInstance {
+        CloudInstance {
+            hostname: "web-1".to_string(),
+            ipv4_address: Some("192.0.2.10".to_string()),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn issues_with_approle_then_keeps_current_certificate() {
+        let (address, received) = start_vault();
+        let directory =
+            std::env::temp_dir().join(format!("instance-init-{}-vault", std::process::id()));
+        let configuration = configuration(address, &directory);
+
+        assert!(provision_vault_certificate(&configuration, &instance())
+            .await
+            .unwrap());
+        {
+            let received = received.lock().unwrap();
+            assert_eq!(received.len(), 2);
+            assert_eq!(received[0].0, "POST /v1/auth/approle/login");
+            assert_eq!(
+                received[0].1,
+                json!({ "role_id": ROLE_ID, "secret_id": SECRET_ID })
+            );
+            assert_eq!(received[1].0, "POST /v1/pki/issue/web");
+            assert_eq!(
+                received[1].1,
+                json!({ "common_name": "web-1", "alt_names": "web-1,api.internal", "ip_sans": "192.0.2.10" })
+            );
+        }
+
+        assert_eq!(secret::redact(CLIENT_TOKEN), "***");
+        assert!(directory.join("key.pem").exists());
+        assert!(fs::read_to_string(directory.join("chain.pem"))
+            .unwrap()
+            .contains("Y2E="));
+
+        // Still current and covering every name, nothing is requested
+        assert!(!provision_vault_certificate(&configuration, &instance())
+            .await
+            .unwrap());
+        assert_eq!(received.lock().unwrap().len(), 2);
+
+        // A new name requires a new certificate
+        let mut configuration = configuration;
+        configuration.alt_names.push("db.internal".to_string());
+        assert!(provision_vault_certificate(&configuration, &instance())
+            .await
+            .unwrap());
+        assert_eq!(received.lock().unwrap().len(), 4);
+
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[tokio::test]
+    async fn rejects_missing_credentials() {
+        let (address, received) = start_vault();
+        let directory = std::env::temp_dir().join(format!(
+            "instance-init-{}-vault-anonymous",
+            std::process::id()
+        ));
+        let mut configuration = configuration(address, &directory);
+        configuration.secret_id = None;
+
+        let result = provision_vault_certificate(&configuration, &instance()).await;
+        assert!(matches!(result, Err(TlsError::AuthenticationError(_))));
+        assert!(received.lock().unwrap().is_empty());
+    }
+}